@@ -0,0 +1,93 @@
+//! Timings of the queries used by the paint tool on a scene with tens of
+//! thousands of triangles, run with `cargo bench --bench spatial`
+use ggez::graphics;
+use ggez::nalgebra as na;
+use rand::prelude::*;
+use rs_type::drawing::{ColoredTriangles, IndexedTriangles};
+use std::time::Instant;
+
+/// Triangles along each side of the scene, two for each square
+const SIDE: usize = 150;
+const WIDTH: f32 = 600.0;
+const HEIGHT: f32 = 800.0;
+
+/// Runs f n times, printing the average duration of a run
+fn bench<F: FnMut(usize)>(name: &str, n: usize, mut f: F) {
+    let start = Instant::now();
+    for i in 0..n {
+        f(i);
+    }
+    let each = start.elapsed().as_secs_f64() / n as f64;
+    println!("{:<20} {:>12.2} us", name, each * 1e6);
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    // A grid of slightly shaken points, shared by neighbouring triangles
+    let (dx, dy) = (WIDTH / SIDE as f32, HEIGHT / SIDE as f32);
+    let mut grid = vec![];
+    for y in 0..=SIDE {
+        for x in 0..=SIDE {
+            grid.push(na::Point2::new(
+                x as f32 * dx + rng.gen_range(-0.2, 0.2) * dx,
+                y as f32 * dy + rng.gen_range(-0.2, 0.2) * dy,
+            ));
+        }
+    }
+    let mut ct = ColoredTriangles {
+        colors: vec![
+            graphics::Color::from_rgb(255, 0, 0),
+            graphics::Color::from_rgb(0, 255, 0),
+            graphics::Color::from_rgb(0, 0, 255),
+        ],
+        triangles: vec![vec![]; 3],
+    };
+    for y in 0..SIDE {
+        for x in 0..SIDE {
+            let p = |x, y| grid[y * (SIDE + 1) + x];
+            let c = (x + y) % 3;
+            ct.triangles[c].extend(&[p(x, y), p(x + 1, y), p(x + 1, y + 1)]);
+            ct.triangles[c].extend(&[p(x, y), p(x + 1, y + 1), p(x, y + 1)]);
+        }
+    }
+    println!("Scene with {} triangles", 2 * SIDE * SIDE);
+
+    let mut it = IndexedTriangles::from(&ct);
+    bench("build", 1, |_| it = IndexedTriangles::from(&ct));
+
+    let queries = (0..10_000)
+        .map(|_| na::Point2::new(rng.gen_range(0.0, WIDTH), rng.gen_range(0.0, HEIGHT)))
+        .collect::<Vec<_>>();
+    bench("nearest", queries.len(), |i| {
+        it.nearest(1, &queries[i]);
+    });
+    bench("colliding", queries.len(), |i| {
+        it.colliding(&queries[i]);
+    });
+    bench("hits", queries.len(), |i| {
+        it.hits(&queries[i]);
+    });
+    bench("inside", 1_000, |i| {
+        let p = queries[i];
+        it.inside(&p, &(p + na::Vector2::new(50.0, 50.0)));
+    });
+    bench("add_triangle", 1_000, |i| {
+        let p = queries[i];
+        let tri = [
+            p,
+            p + na::Vector2::new(10.0, 0.0),
+            p + na::Vector2::new(0.0, 10.0),
+        ];
+        it.add_triangle(i % 3, &tri);
+    });
+    // Like dragging a vertex around with the mouse
+    bench("move_vertices", 1_000, |i| {
+        let v = (i * 97) % it.vertices.len();
+        it.move_vertices(&[(v, it.vertices[v] + na::Vector2::new(1.0, -1.0))]);
+    });
+    bench("remove_triangles", 100, |i| {
+        let c = i % 3;
+        let n = it.triangles[c].len();
+        it.remove_triangles(&[(c, (i * 97) % n)]);
+    });
+}
@@ -1,107 +1,370 @@
 /* This is a game inspired by ztype to learn how to digit
  *
- * - vim mode: exercise esc as well!
  * - backspace mode: when you make a mistake, that is added to enemy's life
  *
  * */
 use ggez::{conf, event, input::keyboard::KeyCode, ContextBuilder};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-use rs_type::{wording, Game, Manager, MenuEntry, Quit, StateMap, States, UI};
+use rs_type::{
+    difficulty::Preset,
+    keymap::{GameAction, Keymaps},
+    menu,
+    settings::{self, Settings, SettingsScreen},
+    wording::{self, Course, WordProducer},
+    Game, Manager, MenuEntry, Payload, Quit, StateChange, StateMap, States, UI,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "stype")]
 struct Options {
-    #[structopt(short, long, default_value = "30.0")]
-    font_size: f32,
+    /// Size of words in game [default: 30]
+    #[structopt(short, long)]
+    font_size: Option<f32>,
 
-    #[structopt(name = "FILE", parse(from_os_str))]
-    file: PathBuf,
+    /// Width of the window [default: 600]
+    #[structopt(long)]
+    width: Option<f32>,
+
+    /// Height of the window [default: 800]
+    #[structopt(long)]
+    height: Option<f32>,
+
+    /// Start in fullscreen
+    #[structopt(long, overrides_with = "no_fullscreen")]
+    fullscreen: bool,
+
+    /// Start in a window, even if the settings say fullscreen
+    #[structopt(long, overrides_with = "fullscreen")]
+    no_fullscreen: bool,
+
+    /// Difficulty: easy, normal, hard or custom (as in the settings)
+    #[structopt(short, long)]
+    difficulty: Option<Preset>,
+
+    /// Adjust the pace of the game to how the player is doing
+    #[structopt(long, overrides_with = "no_dynamic")]
+    dynamic: bool,
+
+    /// Keep the pace of the game, even if the settings say dynamic
+    #[structopt(long, overrides_with = "dynamic")]
+    no_dynamic: bool,
+
+    /// Settings (TOML), instead of rs-type/settings.toml in the configuration directory
+    #[structopt(long, parse(from_os_str))]
+    settings: Option<PathBuf>,
+
+    /// Play the built-in vim drills instead of a course
+    #[structopt(long)]
+    vim: bool,
+
+    #[structopt(name = "FILE", parse(from_os_str), required_unless = "vim")]
+    file: Option<PathBuf>,
+
+    /// Key bindings (TOML), instead of rs-type/keys.toml in the configuration directory
+    #[structopt(long, parse(from_os_str))]
+    keys: Option<PathBuf>,
 }
 
-const WIDTH: f32 = 600.0;
-const HEIGHT: f32 = 800.0;
+/// Value of a flag that can be turned on and off, if given
+fn flag(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Rows shown at once in the lists of courses and lessons
+const LIST_ROWS: usize = 8;
+
+/// Reads the producer of a course file, or the vim drills
+fn load_course(course: &Course) -> Result<Box<dyn WordProducer>, String> {
+    match course {
+        Course::File(path) => Ok(Box::new(wording::KTouchParser::load(path)?)),
+        Course::Vim => Ok(Box::new(wording::VimDrills)),
+    }
+}
+
+/// Entry of a course in the list of courses, opening the list of its lessons
+fn course_entry(name: String, course: Course) -> MenuEntry {
+    let mut lessons = Vec::new();
+    if let Ok(producer) = load_course(&course) {
+        while let Some(lesson) = producer.lesson(lessons.len()) {
+            let title = if lesson.title.is_empty() {
+                format!("Lesson {}", lessons.len() + 1)
+            } else {
+                lesson.title
+            };
+            let payload = Payload::Course(course.clone(), lessons.len());
+            lessons.push(MenuEntry::Item(
+                24.0,
+                title,
+                StateChange::Switch(States::Game, payload),
+            ));
+        }
+    }
+    if lessons.is_empty() {
+        // Nothing to pick, just try to play it
+        let payload = Payload::Course(course, 0);
+        return MenuEntry::Item(30.0, name, StateChange::Switch(States::Game, payload));
+    }
+    MenuEntry::Submenu(
+        30.0,
+        name.clone(),
+        vec![
+            MenuEntry::VSpace(120.0),
+            MenuEntry::Message(50.0, name),
+            MenuEntry::VSpace(30.0),
+            MenuEntry::List(LIST_ROWS, lessons),
+            MenuEntry::VSpace(30.0),
+            MenuEntry::Message(24.0, "[Enter] play lesson, [Esc] back".to_owned()),
+        ],
+    )
+}
+
+/// Menu listing the vim drills and the courses (.xml files) in the folder of
+/// course, each opening the list of its lessons
+fn courses_menu(course: &Path) -> Vec<MenuEntry> {
+    let dir = match course.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension() == Some(std::ffi::OsStr::new("xml")))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    let vim = course_entry("Vim motions".to_owned(), Course::Vim);
+    let courses = std::iter::once(vim)
+        .chain(files.into_iter().map(|file| {
+            let name = file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            course_entry(name, Course::File(file))
+        }))
+        .collect();
+    vec![
+        MenuEntry::VSpace(120.0),
+        MenuEntry::Message(50.0, "Courses".to_owned()),
+        MenuEntry::Details(24.0),
+        MenuEntry::VSpace(30.0),
+        MenuEntry::List(LIST_ROWS, courses),
+        MenuEntry::VSpace(30.0),
+        MenuEntry::Message(24.0, "[Enter] pick lesson".to_owned()),
+        MenuEntry::Transition(
+            24.0,
+            "[Esc] back".to_owned(),
+            KeyCode::Escape,
+            States::StartScreen.into(),
+        ),
+    ]
+}
+
+/// Menu at the end of a level, or of the whole course if there is no next level,
+/// showing how the level went
+fn end_menu(title: &str, next: bool) -> Vec<MenuEntry> {
+    let mut menu = vec![
+        MenuEntry::VSpace(200.0),
+        MenuEntry::Message(70.0, title.to_owned()),
+        MenuEntry::VSpace(30.0),
+        MenuEntry::Details(24.0),
+        MenuEntry::VSpace(30.0),
+    ];
+    if next {
+        menu.push(MenuEntry::Transition(
+            30.0,
+            "[Enter] next lesson".to_owned(),
+            KeyCode::Return,
+            StateChange::Switch(States::Game, Payload::Next),
+        ));
+    }
+    menu.extend(vec![
+        MenuEntry::Transition(
+            30.0,
+            "[R]etry lesson".to_owned(),
+            KeyCode::R,
+            StateChange::Switch(States::Game, Payload::Retry),
+        ),
+        MenuEntry::Transition(
+            30.0,
+            "[C]hoose another course".to_owned(),
+            KeyCode::C,
+            States::Courses.into(),
+        ),
+        MenuEntry::Transition(
+            30.0,
+            "[Esc] main menu".to_owned(),
+            KeyCode::Escape,
+            States::StartScreen.into(),
+        ),
+    ]);
+    menu
+}
 
 fn main() {
     let opt = Options::from_args();
     println!("Options: {:#?}", opt);
 
+    let file = Settings::load(opt.settings.as_deref()).unwrap_or_else(|e| {
+        println!("Unable to load settings, using defaults: {}", e);
+        Settings::default()
+    });
+    // Options given override the settings, but are not saved with them
+    let mut settings = file.clone();
+    if let Some(font_size) = opt.font_size {
+        settings.font_size = font_size;
+    }
+    if let Some(width) = opt.width {
+        settings.width = width;
+    }
+    if let Some(height) = opt.height {
+        settings.height = height;
+    }
+    if let Some(fullscreen) = flag(opt.fullscreen, opt.no_fullscreen) {
+        settings.fullscreen = fullscreen;
+    }
+    if let Some(dynamic) = flag(opt.dynamic, opt.no_dynamic) {
+        settings.dynamic = dynamic;
+    }
+    if let Some(difficulty) = opt.difficulty {
+        settings.difficulty = difficulty;
+    }
+    let fullscreen = if settings.fullscreen {
+        conf::FullscreenType::Desktop
+    } else {
+        conf::FullscreenType::Windowed
+    };
+
     let (mut ctx, mut event_loop) = ContextBuilder::new("rs-type", "Alessandro Re")
         .window_setup(conf::WindowSetup::default().title("RS-Type"))
-        .window_mode(conf::WindowMode::default().dimensions(WIDTH, HEIGHT))
+        .window_mode(
+            conf::WindowMode::default()
+                .dimensions(settings.width, settings.height)
+                .fullscreen_type(fullscreen),
+        )
         //.add_resource_path(resource_dir)
         .build()
         .expect("aieee, could not create ggez context!");
 
+    let keys = Keymaps::load(opt.keys.as_deref()).unwrap_or_else(|e| {
+        println!("Unable to load key bindings, using defaults: {}", e);
+        Keymaps::default()
+    });
+    // The pause key unpauses as well, if it can be shown in the menu
+    let unpause = keys.game.key(GameAction::Pause).unwrap_or(KeyCode::Escape);
+
     // Create a map of states
     let mut states: StateMap = std::collections::HashMap::new();
+    let name = menu::shared(whoami::user());
+    let difficulty = menu::shared(settings.difficulty.index());
     let main_menu = vec![
         MenuEntry::VSpace(120.0),
         MenuEntry::Message(60.0, "RS-Type".to_owned()),
         MenuEntry::VSpace(60.0),
-        MenuEntry::Transition(40.0, "[S]tart game".to_owned(), KeyCode::S, States::Game),
+        MenuEntry::Transition(
+            40.0,
+            "[S]tart game".to_owned(),
+            KeyCode::S,
+            States::Game.into(),
+        ),
+        MenuEntry::Transition(
+            40.0,
+            "[C]hoose course".to_owned(),
+            KeyCode::C,
+            States::Courses.into(),
+        ),
+        MenuEntry::Transition(
+            40.0,
+            "[V]im training".to_owned(),
+            KeyCode::V,
+            StateChange::Switch(States::Game, Payload::Course(Course::Vim, 0)),
+        ),
+        MenuEntry::Transition(
+            40.0,
+            "[O]ptions".to_owned(),
+            KeyCode::O,
+            States::Settings.into(),
+        ),
+        MenuEntry::Input(40.0, "Name".to_owned(), name.clone()),
+        settings::difficulty_choice(40.0, &difficulty),
         MenuEntry::Transition(
             40.0,
             "[Esc] to quit".to_owned(),
             KeyCode::Escape,
-            States::Quit,
+            States::Quit.into(),
         ),
     ];
 
     states.insert(States::StartScreen, Box::new(UI::new(&mut ctx, main_menu)));
+    let course = match &opt.file {
+        Some(file) if !opt.vim => Course::File(file.clone()),
+        _ => Course::Vim,
+    };
+    let producer = load_course(&course).unwrap_or_else(|e| panic!("{}", e));
+    let mut game = Game::new(whoami::user(), producer, settings.font_size, &mut ctx);
+    let settings = menu::shared(settings);
+    game.set_keys(keys.game);
+    game.set_player_name(name);
+    game.set_settings(settings.clone());
+    game.set_difficulty(difficulty.clone());
+    game.set_course(course, load_course);
+    states.insert(States::Game, Box::new(game));
     states.insert(
-        States::Game,
-        Box::new(Game::new(
-            whoami::user(),
-            wording::KTouchParser::new(opt.file.as_path()),
-            opt.font_size,
+        States::Settings,
+        Box::new(SettingsScreen::new(
             &mut ctx,
+            file,
+            settings,
+            difficulty,
+            opt.settings.clone(),
         )),
     );
     states.insert(
-        States::Pause,
+        States::Courses,
         Box::new(UI::new(
+            &mut ctx,
+            courses_menu(opt.file.as_deref().unwrap_or_else(|| Path::new("."))),
+        )),
+    );
+    states.insert(
+        States::Pause,
+        Box::new(UI::overlay(
             &mut ctx,
             vec![
                 MenuEntry::VSpace(400.0),
                 MenuEntry::Message(70.0, "PAUSED".to_owned()),
                 MenuEntry::Transition(
                     30.0,
-                    "[Esc] to unpause".to_owned(),
-                    KeyCode::Escape,
-                    States::Game,
+                    format!("[{:?}] to unpause", unpause),
+                    unpause,
+                    StateChange::Pop(Payload::None),
                 ),
                 MenuEntry::Transition(
                     30.0,
                     "[Q] to quit game".to_owned(),
                     KeyCode::Q,
-                    States::Quit,
+                    States::Quit.into(),
                 ),
             ],
         )),
     );
     states.insert(
         States::NextLevel,
-        Box::new(UI::new(
-            &mut ctx,
-            vec![
-                MenuEntry::VSpace(300.0),
-                MenuEntry::Message(70.0, "Level".to_owned()),
-                MenuEntry::Message(70.0, "Complete".to_owned()),
-            ],
-        )),
+        Box::new(UI::new(&mut ctx, end_menu("Level Complete", true))),
     );
     states.insert(
         States::Victory,
-        Box::new(UI::new(
-            &mut ctx,
-            vec![
-                MenuEntry::VSpace(300.0),
-                MenuEntry::Message(70.0, "YOU".to_owned()),
-                MenuEntry::Message(70.0, "WON".to_owned()),
-            ],
-        )),
+        Box::new(UI::new(&mut ctx, end_menu("YOU WON", false))),
     );
     states.insert(States::Quit, Box::new(Quit {}));
 
@@ -4,10 +4,20 @@
  * - backspace mode: when you make a mistake, that is added to enemy's life
  *
  * */
-use ggez::{conf, event, ContextBuilder};
+use ggez::{
+    conf, event,
+    graphics::{Color, Rect},
+    ContextBuilder,
+};
+use std::path::PathBuf;
 use structopt::StructOpt;
 
-use rs_type::{Manager, Paint, StateMap, States};
+use rs_type::{
+    drawing::{str2col, ColoredTriangles},
+    keymap::Keymaps,
+    palette::Palette,
+    write_atomic, Manager, Paint, StateMap, States,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "stype")]
@@ -15,8 +25,12 @@ struct Options {
     #[structopt()]
     paint_file: String,
 
-    #[structopt(long, name = "colors", min_values = 1)]
-    paint_colors: Vec<String>,
+    #[structopt(long, name = "colors", min_values = 1, parse(try_from_str = str2col))]
+    paint_colors: Vec<Color>,
+
+    /// GIMP palette (.gpl) with colors to add
+    #[structopt(long, parse(from_os_str))]
+    palette: Option<PathBuf>,
 
     #[structopt(long, default_value = "15.0")]
     margin: f32,
@@ -32,11 +46,101 @@ struct Options {
 
     #[structopt(long)]
     write_absolute: bool,
+
+    /// Snap to a grid with this spacing, in pixels
+    #[structopt(long, parse(try_from_str = parse_grid))]
+    grid: Option<f32>,
+
+    /// Image to trace over, stretched over the drawing area
+    #[structopt(long, parse(from_os_str))]
+    reference: Option<PathBuf>,
+
+    /// Opacity of the reference image, from 0 to 1
+    #[structopt(long, default_value = "0.5")]
+    reference_opacity: f32,
+
+    /// Key bindings (TOML), instead of rs-type/keys.toml in the configuration directory
+    #[structopt(long, parse(from_os_str))]
+    keys: Option<PathBuf>,
+
+    /// Report problems in the file, like triangles with no area, and exit
+    #[structopt(long)]
+    check: bool,
+
+    /// Repair problems in the file, saving it, and exit
+    #[structopt(long)]
+    fix: bool,
+
+    /// With --fix, move vertices out of the drawing area to its border
+    #[structopt(long)]
+    clamp: bool,
+
+    /// Vertices closer than this (in file coordinates) are welded by --fix
+    #[structopt(long, default_value = "0.0001")]
+    epsilon: f32,
+}
+
+/// Parses the spacing of the grid, which must be positive
+fn parse_grid(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(size) if size > 0.0 && size.is_finite() => Ok(size),
+        Ok(_) => Err(format!("grid spacing must be positive, got {}", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Checks the file and, with --fix, repairs it; returns the exit code
+fn check(opt: &Options) -> i32 {
+    let data = match std::fs::read_to_string(&opt.paint_file) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Unable to read {}: {}", opt.paint_file, e);
+            return 2;
+        }
+    };
+    let mut ct: ColoredTriangles = match data.parse() {
+        Ok(ct) => ct,
+        Err(e) => {
+            println!("Unable to parse {}: {}", opt.paint_file, e);
+            return 2;
+        }
+    };
+    // Relative files span 0 to 1, absolute ones the drawing area
+    let bounds = if opt.read_absolute {
+        Rect::new(0.0, 0.0, opt.width, opt.height)
+    } else {
+        Rect::new(0.0, 0.0, 1.0, 1.0)
+    };
+    let report = if opt.fix {
+        ct.fix(opt.epsilon, &bounds, opt.clamp)
+    } else {
+        ct.check(opt.epsilon, &bounds)
+    };
+    println!("{}", report);
+    if !opt.fix {
+        return if report.is_empty() { 0 } else { 1 };
+    }
+    if report.is_empty() {
+        return 0;
+    }
+    match write_atomic(&opt.paint_file, &ct.to_string()) {
+        Ok(()) => {
+            println!("Fixed {}", opt.paint_file);
+            0
+        }
+        Err(e) => {
+            println!("Unable to write {}: {}", opt.paint_file, e);
+            2
+        }
+    }
 }
 
 fn main() {
     let opt = Options::from_args();
     println!("Options: {:#?}", opt);
+    if opt.check || opt.fix {
+        std::process::exit(check(&opt));
+    }
 
     let (mut ctx, mut event_loop) = ContextBuilder::new("rs-type", "Alessandro Re")
         .window_setup(conf::WindowSetup::default().title("Paint Triangles"))
@@ -49,19 +153,46 @@ fn main() {
 
     ggez::input::mouse::set_cursor_hidden(&mut ctx, true);
 
+    let paint_file = opt.paint_file.clone();
+    let mut paint = match Paint::new(
+        &mut ctx,
+        opt.margin,
+        opt.paint_colors,
+        opt.paint_file,
+        opt.read_absolute,
+        opt.write_absolute,
+    ) {
+        Ok(paint) => paint,
+        Err(e) => {
+            println!("Unable to parse {}: {}", paint_file, e);
+            std::process::exit(2);
+        }
+    };
+    if let Some(size) = opt.grid {
+        paint.set_grid(size);
+    }
+    if let Some(path) = &opt.palette {
+        let palette = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| s.parse::<Palette>());
+        match palette {
+            Ok(palette) => paint.add_palette(&palette),
+            Err(e) => println!("Unable to load palette {}: {}", path.display(), e),
+        }
+    }
+    match Keymaps::load(opt.keys.as_deref()) {
+        Ok(keys) => paint.set_keys(keys.paint),
+        Err(e) => println!("Unable to load key bindings, using defaults: {}", e),
+    }
+    if let Some(path) = &opt.reference {
+        if let Err(e) = paint.set_reference(&mut ctx, path, opt.reference_opacity) {
+            println!("Unable to load reference {}: {}", path.display(), e);
+        }
+    }
+
     // Create a map of states
     let mut states: StateMap = std::collections::HashMap::new();
-    states.insert(
-        States::Paint,
-        Box::new(Paint::new(
-            &mut ctx,
-            opt.margin,
-            opt.paint_colors,
-            opt.paint_file,
-            opt.read_absolute,
-            opt.write_absolute,
-        )),
-    );
+    states.insert(States::Paint, Box::new(paint));
 
     let mut state = Manager::new(&mut ctx, States::Paint, states);
 
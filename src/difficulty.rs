@@ -0,0 +1,395 @@
+//! How hard the game is: how fast enemies get and how often they come, level
+//! after level, as named presets or custom values from the settings
+
+use crate::stats::WORD_LENGTH;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Length of words (in characters) whose speed is not scaled by their length
+const REFERENCE_LENGTH: f32 = 5.0;
+/// Bounds of the factor scaling the speed of words by their length
+const LENGTH_FACTOR: (f32, f32) = (0.5, 1.5);
+/// Seconds of keystrokes kept to measure how the player is typing
+const ROLLING_SECS: u64 = 20;
+/// Keystrokes needed before accuracy is considered
+const MIN_KEYS: usize = 10;
+/// Fraction of the characters coming the player must type, not to be behind
+const KEEP_UP: f32 = 0.9;
+/// Change of pace at each adjustment (each frame)
+const PACE_STEP: f32 = 0.002;
+/// Bounds of the pace
+const PACE_RANGE: (f32, f32) = (0.25, 4.0);
+
+/// Named difficulties, the custom one is in the settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    Easy,
+    Normal,
+    Hard,
+    Custom,
+}
+
+impl Preset {
+    /// Every preset, easiest first
+    pub const ALL: [Preset; 4] = [Preset::Easy, Preset::Normal, Preset::Hard, Preset::Custom];
+
+    /// Position in ALL
+    pub fn index(self) -> usize {
+        Preset::ALL.iter().position(|&p| p == self).unwrap()
+    }
+
+    /// Values of the preset, None for the custom one
+    pub fn difficulty(self) -> Option<Difficulty> {
+        match self {
+            Preset::Easy => Some(Difficulty {
+                speed: 0.3,
+                speed_growth: 0.5,
+                spawn_secs: 3.0,
+                spawn_decay: 0.95,
+                min_spawn_secs: 1.5,
+                max_enemies: 4,
+                length_scaling: true,
+            }),
+            Preset::Normal => Some(Difficulty::default()),
+            Preset::Hard => Some(Difficulty {
+                speed: 0.6,
+                speed_growth: 1.0,
+                spawn_secs: 1.5,
+                spawn_decay: 0.85,
+                min_spawn_secs: 0.5,
+                max_enemies: 12,
+                length_scaling: false,
+            }),
+            Preset::Custom => None,
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Preset::Easy => "easy",
+            Preset::Normal => "normal",
+            Preset::Hard => "hard",
+            Preset::Custom => "custom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Preset::ALL
+            .iter()
+            .find(|p| p.to_string().eq_ignore_ascii_case(s.trim()))
+            .cloned()
+            .ok_or_else(|| format!("unknown difficulty {:?}", s))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difficulty {
+    /// Pixels enemies move down each frame, in the first level
+    pub speed: f32,
+    /// Speed added at each level, as a fraction of the first one
+    pub speed_growth: f32,
+    /// Seconds between enemies appearing, in the first level
+    pub spawn_secs: f32,
+    /// Factor of the seconds between enemies at each level
+    pub spawn_decay: f32,
+    /// Fewest seconds between enemies, however far the levels go
+    pub min_spawn_secs: f32,
+    /// Most enemies on screen at once
+    pub max_enemies: usize,
+    /// Whether long words are slower and short ones faster, as long words
+    /// take longer to type
+    pub length_scaling: bool,
+}
+
+/// The normal difficulty
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty {
+            speed: 0.5,
+            speed_growth: 1.0,
+            spawn_secs: 2.0,
+            spawn_decay: 0.95,
+            min_spawn_secs: 1.0,
+            max_enemies: 8,
+            length_scaling: false,
+        }
+    }
+}
+
+impl Difficulty {
+    /// Speed of a word of some length appearing in a level
+    pub fn speed(&self, level: usize, length: usize) -> f32 {
+        let speed = self.speed * (1.0 + self.speed_growth * level as f32);
+        if self.length_scaling {
+            let factor = REFERENCE_LENGTH / length.max(1) as f32;
+            speed * factor.clamp(LENGTH_FACTOR.0, LENGTH_FACTOR.1)
+        } else {
+            speed
+        }
+    }
+
+    /// Seconds between enemies appearing in a level
+    pub fn spawn_secs(&self, level: usize) -> f32 {
+        let secs = self.spawn_secs * self.spawn_decay.powi(level as i32);
+        secs.max(self.min_spawn_secs)
+    }
+}
+
+/// Words (of 5 characters) per minute, for characters since first
+fn per_minute(first: Option<Instant>, now: Instant, chars: usize) -> f32 {
+    let first = match first {
+        Some(t) => t,
+        None => return 0.0,
+    };
+    // At least a second, not to count a burst of keys as a fast pace
+    let minutes = now.duration_since(first).as_secs_f32().max(1.0) / 60.0;
+    chars as f32 / WORD_LENGTH / minutes
+}
+
+/// Adjusts the pace of the game to keep the player near a target accuracy and
+/// pressure, from the keystrokes of the last seconds
+#[derive(Clone, Debug)]
+pub struct Adaptive {
+    /// When keys were typed, and whether they were right
+    keys: VecDeque<(Instant, bool)>,
+    /// When words came, and their length
+    words: VecDeque<(Instant, usize)>,
+    /// Factor of the speed and spawn rate of enemies
+    pub pace: f32,
+    /// Fraction of keystrokes typed right to keep
+    pub target_accuracy: f32,
+    /// Fraction of the screen to keep the lowest enemy at
+    pub target_pressure: f32,
+}
+
+impl Adaptive {
+    pub fn new(target_accuracy: f32, target_pressure: f32) -> Adaptive {
+        Adaptive {
+            keys: VecDeque::new(),
+            words: VecDeque::new(),
+            pace: 1.0,
+            target_accuracy,
+            target_pressure,
+        }
+    }
+
+    /// Records a keystroke, right or wrong
+    pub fn key(&mut self, now: Instant, right: bool) {
+        self.keys.push_back((now, right));
+        self.forget(now);
+    }
+
+    /// Records a word coming, of some characters
+    pub fn word(&mut self, now: Instant, length: usize) {
+        self.words.push_back((now, length));
+        self.forget(now);
+    }
+
+    /// Moves keystrokes and words later, as if the time paused never passed
+    pub fn shift(&mut self, paused: Duration) {
+        for (t, _) in self.keys.iter_mut() {
+            *t += paused;
+        }
+        for (t, _) in self.words.iter_mut() {
+            *t += paused;
+        }
+    }
+
+    /// Drops keystrokes and words older than the rolling window
+    fn forget(&mut self, now: Instant) {
+        let window = Duration::from_secs(ROLLING_SECS);
+        while let Some(&(t, _)) = self.keys.front() {
+            if now.duration_since(t) <= window {
+                break;
+            }
+            self.keys.pop_front();
+        }
+        while let Some(&(t, _)) = self.words.front() {
+            if now.duration_since(t) <= window {
+                break;
+            }
+            self.words.pop_front();
+        }
+    }
+
+    /// Words (of 5 characters) typed right per minute, in the last seconds
+    pub fn wpm(&self, now: Instant) -> f32 {
+        let right = self.keys.iter().filter(|(_, right)| *right).count();
+        per_minute(self.keys.front().map(|&(t, _)| t), now, right)
+    }
+
+    /// Words (of 5 characters) coming per minute, in the last seconds
+    pub fn demand(&self, now: Instant) -> f32 {
+        let chars = self.words.iter().map(|&(_, n)| n).sum();
+        per_minute(self.words.front().map(|&(t, _)| t), now, chars)
+    }
+
+    /// Fraction of keystrokes typed wrong in the last seconds
+    pub fn error_rate(&self) -> f32 {
+        if self.keys.is_empty() {
+            return 0.0;
+        }
+        let wrong = self.keys.iter().filter(|(_, right)| !*right).count();
+        wrong as f32 / self.keys.len() as f32
+    }
+
+    /// Slows the pace down a step when the lowest enemy is past the target
+    /// pressure (as a fraction of the screen), the player makes too many
+    /// errors or types slower than words come, speeds it up a step otherwise
+    pub fn adjust(&mut self, now: Instant, pressure: f32) {
+        self.forget(now);
+        let measured = self.keys.len() >= MIN_KEYS;
+        let sloppy = measured && 1.0 - self.error_rate() < self.target_accuracy;
+        let behind = measured && self.wpm(now) < KEEP_UP * self.demand(now);
+        let step = if sloppy || behind || pressure > self.target_pressure {
+            1.0 - PACE_STEP
+        } else {
+            1.0 + PACE_STEP
+        };
+        self.pace = (self.pace * step).clamp(PACE_RANGE.0, PACE_RANGE.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adaptive, Difficulty, Preset};
+    use std::time::{Duration, Instant};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn speed_grows_with_levels() {
+        let normal = Difficulty::default();
+        // As fast as the game always was
+        for level in 0..5 {
+            assert!(close(normal.speed(level, 7), 0.5 * (1 + level) as f32));
+        }
+        let easy = Preset::Easy.difficulty().unwrap();
+        assert!(close(easy.speed(0, 5), 0.3));
+        assert!(close(easy.speed(2, 5), 0.6));
+        // Longer words are slower, within bounds
+        assert!(close(easy.speed(0, 10), 0.15));
+        assert!(close(easy.speed(0, 40), 0.15));
+        assert!(close(easy.speed(0, 1), 0.45));
+        assert!(close(easy.speed(0, 0), 0.45));
+        for level in 0..10 {
+            let hard = Preset::Hard.difficulty().unwrap();
+            assert!(easy.speed(level, 5) < normal.speed(level, 5));
+            assert!(normal.speed(level, 5) < hard.speed(level, 5));
+        }
+    }
+
+    #[test]
+    fn spawns_get_closer_with_levels() {
+        let normal = Difficulty::default();
+        assert!(close(normal.spawn_secs(0), 2.0));
+        assert!(close(normal.spawn_secs(1), 1.9));
+        assert!(close(normal.spawn_secs(2), 1.805));
+        // Down to the fewest seconds
+        assert!(close(normal.spawn_secs(100), 1.0));
+        let fixed = Difficulty {
+            spawn_decay: 1.0,
+            ..Difficulty::default()
+        };
+        assert!(close(fixed.spawn_secs(50), 2.0));
+        for level in 0..20 {
+            assert!(normal.spawn_secs(level + 1) <= normal.spawn_secs(level));
+        }
+    }
+
+    #[test]
+    fn presets_are_named() {
+        for &preset in Preset::ALL.iter() {
+            assert_eq!(preset.to_string().parse(), Ok(preset));
+            assert_eq!(Preset::ALL[preset.index()], preset);
+        }
+        assert_eq!("Hard".parse(), Ok(Preset::Hard));
+        assert!("impossible".parse::<Preset>().is_err());
+        assert_eq!(Preset::Custom.difficulty(), None);
+    }
+
+    #[test]
+    fn pace_follows_the_player() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut adaptive = Adaptive::new(0.9, 0.7);
+        assert_eq!(adaptive.wpm(start), 0.0);
+        assert_eq!(adaptive.error_rate(), 0.0);
+
+        // 60 right keys in 12 seconds are 60 WPM
+        for i in 0..60 {
+            adaptive.key(at(i * 200), true);
+        }
+        assert!((adaptive.wpm(at(12_000)) - 60.0).abs() < 1e-3);
+        // Enemies far from the player, with no errors: faster
+        for i in 0..100 {
+            adaptive.adjust(at(12_000 + i), 0.3);
+        }
+        let fast = adaptive.pace;
+        assert!(fast > 1.0);
+        // Too close to the player: slower
+        adaptive.adjust(at(12_200), 0.8);
+        assert!(adaptive.pace < fast);
+
+        // 20% of errors is below the target accuracy: slower, far as enemies are
+        for i in 0..15 {
+            adaptive.key(at(12_300 + i), false);
+        }
+        assert!((adaptive.error_rate() - 0.2).abs() < 1e-3);
+        let pace = adaptive.pace;
+        adaptive.adjust(at(12_400), 0.1);
+        assert!(adaptive.pace < pace);
+
+        // Old keystrokes are forgotten
+        adaptive.adjust(at(40_000), 0.1);
+        assert_eq!(adaptive.error_rate(), 0.0);
+        assert_eq!(adaptive.wpm(at(40_000)), 0.0);
+
+        // Paused time doesn't pass for the keys
+        adaptive.key(at(40_000), true);
+        adaptive.shift(Duration::from_secs(60));
+        adaptive.adjust(at(100_000), 0.1);
+        assert_eq!(adaptive.keys.len(), 1);
+
+        // The pace stays in bounds
+        for _ in 0..10_000 {
+            adaptive.adjust(at(100_000), 1.0);
+        }
+        assert!((adaptive.pace - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pace_follows_the_words_typed() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut adaptive = Adaptive::new(0.9, 0.7);
+        // Typing at 60 WPM while 3 words of 5 characters came in 12 seconds
+        for i in 0..60 {
+            adaptive.key(at(i * 200), true);
+        }
+        for i in 0..3 {
+            adaptive.word(at(i * 5_000), 5);
+        }
+        assert!((adaptive.demand(at(12_000)) - 15.0).abs() < 1e-3);
+        adaptive.adjust(at(12_000), 0.1);
+        assert!(adaptive.pace > 1.0);
+        // Words come faster than they are typed: slower, far as enemies are
+        for i in 0..50 {
+            adaptive.word(at(12_000 + i), 10);
+        }
+        let pace = adaptive.pace;
+        adaptive.adjust(at(12_100), 0.1);
+        assert!(adaptive.pace < pace);
+    }
+}
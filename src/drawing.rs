@@ -1,22 +1,265 @@
+use crate::spatial::Grid;
 use ggez::graphics;
 use ggez::nalgebra as na;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::str::FromStr;
 
-pub fn str2col(s: &str) -> graphics::Color {
-    let rgb = s
-        .trim_start_matches('#')
-        .chars()
-        .collect::<Vec<char>>()
-        .chunks(2)
-        .map(|c| {
-            u8::from_str_radix(c.iter().collect::<String>().as_str(), 16)
-                .expect("Unable to parse color")
-        })
-        .collect::<Vec<u8>>();
-    graphics::Color::from_rgb(rgb[0], rgb[1], rgb[2])
+/// Side of the cells of the spatial index when there are no triangles to measure
+const DEFAULT_CELL: f32 = 32.0;
+
+/// CSS named colors, as 0xrrggbb
+const CSS_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+/// Why a string is not a color
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorError {
+    /// Hex colors have 3, 4, 6 or 8 digits
+    HexLength(usize),
+    /// A character that is not a hex digit
+    HexDigit(char),
+    /// rgb() and rgba() have 3 or 4 values, separated by commas
+    Arguments(usize),
+    /// A value of rgb() or rgba() not in range
+    Component(String),
+    /// Not a CSS color name
+    UnknownName(String),
+}
+
+impl fmt::Display for ColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorError::HexLength(n) => write!(f, "expected 3, 4, 6 or 8 hex digits, got {}", n),
+            ColorError::HexDigit(c) => write!(f, "'{}' is not a hex digit", c),
+            ColorError::Arguments(n) => write!(f, "expected 3 or 4 values, got {}", n),
+            ColorError::Component(v) => write!(f, "'{}' is not a valid value", v),
+            ColorError::UnknownName(s) => write!(f, "unknown color '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for ColorError {}
+
+/// Parses a color as #rgb, #rgba, #rrggbb, #rrggbbaa, rgb(r, g, b),
+/// rgba(r, g, b, a) or a CSS color name. Values of rgb() are from 0 to 255 or
+/// percentages, alpha is from 0 to 1 or a percentage
+pub fn str2col(s: &str) -> Result<graphics::Color, ColorError> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix('#') {
+        let digits = hex
+            .chars()
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|d| d as u8)
+                    .ok_or(ColorError::HexDigit(c))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // Short forms repeat each digit, so f is ff
+        let rgba = match digits.len() {
+            3 | 4 => digits.iter().map(|d| d * 17).collect::<Vec<_>>(),
+            6 | 8 => digits.chunks(2).map(|d| d[0] * 16 + d[1]).collect(),
+            n => return Err(ColorError::HexLength(n)),
+        };
+        let a = rgba.get(3).cloned().unwrap_or(255);
+        return Ok(graphics::Color::from_rgba(rgba[0], rgba[1], rgba[2], a));
+    }
+    let lower = s.to_lowercase();
+    let args = ["rgba(", "rgb("]
+        .iter()
+        .find(|f| lower.starts_with(*f))
+        .and_then(|f| lower[f.len()..].strip_suffix(')'));
+    if let Some(args) = args {
+        let args = args.split(',').map(str::trim).collect::<Vec<_>>();
+        if args.len() != 3 && args.len() != 4 {
+            return Err(ColorError::Arguments(args.len()));
+        }
+        // Value from 0 to 1, with max being the value meaning 1
+        let value = |v: &str, max: f32| {
+            let x = match v.strip_suffix('%') {
+                Some(p) => p.parse::<f32>().map(|p| p / 100.0),
+                None => v.parse::<f32>().map(|x| x / max),
+            };
+            match x {
+                Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+                _ => Err(ColorError::Component(v.to_owned())),
+            }
+        };
+        let a = match args.get(3) {
+            Some(a) => value(a, 1.0)?,
+            None => 1.0,
+        };
+        return Ok(graphics::Color::new(
+            value(args[0], 255.0)?,
+            value(args[1], 255.0)?,
+            value(args[2], 255.0)?,
+            a,
+        ));
+    }
+    CSS_COLORS
+        .iter()
+        .find(|(name, _)| *name == lower)
+        .map(|&(_, rgb)| graphics::Color::from_rgb_u32(rgb))
+        .ok_or(ColorError::UnknownName(s.to_owned()))
+}
+
+/// Color as #rrggbb, or #rrggbbaa if not opaque
+pub fn col2str(c: graphics::Color) -> String {
+    match c.to_rgba() {
+        (r, g, b, 255) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        (r, g, b, a) => format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ColoredTriangles {
     /// Colors that can be used to draw triangles
     pub colors: Vec<graphics::Color>,
@@ -25,8 +268,7 @@ pub struct ColoredTriangles {
 }
 
 impl ColoredTriangles {
-    pub fn add_color(&mut self, c: &str) {
-        let color = str2col(c);
+    pub fn add_color(&mut self, color: graphics::Color) {
         if self
             .colors
             .iter()
@@ -39,116 +281,205 @@ impl ColoredTriangles {
         }
     }
 
-    /// Removes the 3 vertices corresponding to ith triangle for given color index c
-    pub fn remove_triangle(&mut self, c: usize, i: usize) {
-        let _ = self.triangles[c]
-            .drain(i * 3..(i + 1) * 3)
-            .collect::<Vec<_>>();
+    /// In place scale of triangles, multiplying each axis by a scale factor
+    pub fn scale(&mut self, x_scale: f32, y_scale: f32) {
+        for c in 0..self.triangles.len() {
+            for i in 0..self.triangles[c].len() {
+                self.triangles[c][i].x *= x_scale;
+                self.triangles[c][i].y *= y_scale;
+            }
+        }
     }
 
-    /// Indices of the n nearest points and their (squared) distances from point
-    pub fn nearest(&self, n: usize, point: &na::Point2<f32>) -> Vec<(usize, usize, f32)> {
-        // Compute distance from point to every point in triangles
-        let mut distances: Vec<(usize, usize, f32)> = self
-            .triangles
-            .iter()
-            .enumerate()
-            .flat_map(|(c, v)| {
-                v.iter()
-                    .map(|p| na::distance_squared(p, point))
-                    .enumerate()
-                    .map(move |(i, d)| (c, i, d))
-            })
-            .collect::<Vec<_>>();
-        // Sort distances
-        distances
-            .as_mut_slice()
-            .sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+    /// Finds problems that fix would repair, without changing anything
+    pub fn check(&self, epsilon: f32, bounds: &graphics::Rect) -> SceneReport {
+        self.clone().fix(epsilon, bounds, false)
+    }
 
-        distances.iter().take(n).cloned().collect()
+    /// Welds vertices closer than epsilon to a vertex found before them, then
+    /// removes triangles thinner than epsilon and turns the others clockwise
+    /// (on screen, where y points down). Vertices out of bounds are moved to
+    /// the border if clamp is set. Returns what was found, with triangles
+    /// indexed as they were before removing any
+    pub fn fix(&mut self, epsilon: f32, bounds: &graphics::Rect, clamp: bool) -> SceneReport {
+        let mut report = SceneReport::default();
+        // Vertices are welded to the first vertex near them, using a grid to
+        // look only at vertices in cells next to each one (cells can't be empty)
+        let mut kept: Vec<na::Point2<f32>> = vec![];
+        let mut grid = Grid::new(epsilon.max(1e-6));
+        let mut welds = HashMap::new();
+        let mut seen = HashSet::new();
+        for &p in self.triangles.iter().flatten() {
+            if !seen.insert(point_key(&p)) {
+                continue;
+            }
+            let d = na::Vector2::new(epsilon, epsilon);
+            let near = grid
+                .within(&(p - d), &(p + d))
+                .into_iter()
+                .find(|&k: &usize| na::distance(&kept[k], &p) <= epsilon);
+            match near {
+                Some(k) if kept[k] != p => {
+                    report.welded.push((p, kept[k]));
+                    welds.insert(point_key(&p), kept[k]);
+                }
+                _ => {
+                    grid.insert(kept.len(), &p, &p);
+                    kept.push(p);
+                }
+            }
+            if p.x < bounds.x
+                || p.y < bounds.y
+                || p.x > bounds.x + bounds.w
+                || p.y > bounds.y + bounds.h
+            {
+                report.out_of_bounds.push(p);
+            }
+        }
+        for p in self.triangles.iter_mut().flatten() {
+            if let Some(&q) = welds.get(&point_key(p)) {
+                *p = q;
+            }
+            if clamp {
+                p.x = p.x.clamp(bounds.x, bounds.x + bounds.w);
+                p.y = p.y.clamp(bounds.y, bounds.y + bounds.h);
+            }
+        }
+        for (c, points) in self.triangles.iter_mut().enumerate() {
+            let mut fixed = Vec::with_capacity(points.len());
+            for (i, t) in points.chunks_exact(3).enumerate() {
+                // Twice the area, over the longest side, is the height of the triangle
+                let area = cross(&t[0], &t[1], &t[2]);
+                let longest = (0..3)
+                    .map(|k| na::distance(&t[k], &t[(k + 1) % 3]))
+                    .fold(0.0, f32::max);
+                if area.abs() <= epsilon * longest {
+                    report.degenerate.push((c, i));
+                } else if area < 0.0 {
+                    report.reversed.push((c, i));
+                    fixed.extend_from_slice(&[t[0], t[2], t[1]]);
+                } else {
+                    fixed.extend_from_slice(t);
+                }
+            }
+            *points = fixed;
+        }
+        report
     }
+}
 
-    /// Returns indices of the first triangle found containing the point, if any
-    pub fn colliding(&self, point: &na::Point2<f32>) -> Option<(usize, usize)> {
-        // https://stackoverflow.com/questions/2049582/how-to-determine-if-a-point-is-in-a-2d-triangle
-        fn sign(p1: &na::Point2<f32>, p2: &na::Point2<f32>, p3: &na::Point2<f32>) -> f32 {
-            (p1.x - p3.x) * (p2.y - p3.y) - (p2.x - p3.x) * (p1.y - p3.y)
-        }
-
-        let contains = |p1: &na::Point2<f32>, p2: &na::Point2<f32>, p3: &na::Point2<f32>| {
-            let d1 = sign(point, p1, p2);
-            let d2 = sign(point, p2, p3);
-            let d3 = sign(point, p3, p1);
-            let has_neg = (d1 < 0.0) || (d2 < 0.0) || (d3 < 0.0);
-            let has_pos = (d1 > 0.0) || (d2 > 0.0) || (d3 > 0.0);
-            !(has_neg && has_pos)
-        };
+/// Problems found in a scene by ColoredTriangles::check or fix
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneReport {
+    /// Vertices closer than epsilon to another vertex, and that vertex
+    pub welded: Vec<(na::Point2<f32>, na::Point2<f32>)>,
+    /// Triangles thinner than epsilon (after welding), as (color, triangle index)
+    pub degenerate: Vec<(usize, usize)>,
+    /// Vertices out of bounds
+    pub out_of_bounds: Vec<na::Point2<f32>>,
+    /// Triangles turning counterclockwise on screen, as (color, triangle index)
+    pub reversed: Vec<(usize, usize)>,
+}
 
-        let collisions: Vec<(usize, usize)> = self
-            .triangles
-            .iter()
-            .enumerate()
-            .flat_map(|(c, v)| {
-                v.as_slice()
-                    .chunks(3)
-                    .enumerate()
-                    .filter_map(move |(i_tr, chunk)| {
-                        if contains(&chunk[0], &chunk[1], &chunk[2]) {
-                            Some((c, i_tr))
-                        } else {
-                            None
-                        }
-                    })
-            })
-            .collect();
-        if collisions.is_empty() {
-            None
-        } else {
-            Some(collisions[0])
-        }
+impl SceneReport {
+    /// True if no problem was found
+    pub fn is_empty(&self) -> bool {
+        self.welded.is_empty()
+            && self.degenerate.is_empty()
+            && self.out_of_bounds.is_empty()
+            && self.reversed.is_empty()
     }
+}
 
-    /// In place scale of triangles, multiplying each axis by a scale factor
-    pub fn scale(&mut self, x_scale: f32, y_scale: f32) {
-        for c in 0..self.triangles.len() {
-            for i in 0..self.triangles[c].len() {
-                self.triangles[c][i].x *= x_scale;
-                self.triangles[c][i].y *= y_scale;
-            }
+impl fmt::Display for SceneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (p, q) in &self.welded {
+            writeln!(f, "vertex {},{} is near vertex {},{}", p.x, p.y, q.x, q.y)?;
+        }
+        for (c, i) in &self.degenerate {
+            writeln!(f, "triangle {} of color {} has no area", i, c)?;
+        }
+        for p in &self.out_of_bounds {
+            writeln!(f, "vertex {},{} is out of bounds", p.x, p.y)?;
+        }
+        for (c, i) in &self.reversed {
+            writeln!(f, "triangle {} of color {} turns counterclockwise", i, c)?;
         }
+        write!(
+            f,
+            "{} vertices to weld, {} triangles with no area, {} vertices out of bounds, {} triangles to turn",
+            self.welded.len(),
+            self.degenerate.len(),
+            self.out_of_bounds.len(),
+            self.reversed.len(),
+        )
     }
+}
 
-    /// In place translation of triangles, adding to each axis an offset
-    pub fn translate(&mut self, x_off: f32, y_off: f32) {
-        for c in 0..self.triangles.len() {
-            for i in 0..self.triangles[c].len() {
-                self.triangles[c][i].x += x_off;
-                self.triangles[c][i].y += y_off;
+/// Why a scene file can't be read, with the line (from 1) of the problem
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneError {
+    /// Neither an RGB number nor a color str2col parses
+    Color(usize, ColorError),
+    /// Not two numbers separated by a comma
+    Point(usize, String),
+    /// The points of a line aren't a whole number of triangles
+    Triangles(usize, usize),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Color(l, e) => write!(f, "line {}: {}", l, e),
+            SceneError::Point(l, p) => write!(f, "line {}: '{}' is not a point", l, p),
+            SceneError::Triangles(l, n) => {
+                write!(
+                    f,
+                    "line {}: {} points are not a whole number of triangles",
+                    l, n
+                )
             }
         }
     }
 }
 
-impl From<&str> for ColoredTriangles {
-    fn from(s: &str) -> Self {
+impl std::error::Error for SceneError {}
+
+/// Parses a point as x,y
+fn parse_point(tok: &str) -> Option<na::Point2<f32>> {
+    let mut coords = tok.split(',').map(|v| v.parse::<f32>());
+    match (coords.next(), coords.next(), coords.next()) {
+        (Some(Ok(x)), Some(Ok(y)), None) => Some(na::Point2::new(x, y)),
+        _ => None,
+    }
+}
+
+impl FromStr for ColoredTriangles {
+    type Err = SceneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut ct = Self {
             colors: vec![],
             triangles: vec![],
         };
-        s.lines().for_each(|l| {
-            let mut data: Vec<_> = l.split_whitespace().collect();
-            let col = data
-                .remove(0)
-                .parse::<u32>()
-                .expect("Unable to parse color");
-            let col = graphics::Color::from_rgb_u32(col);
+        for (i, l) in s.lines().enumerate() {
+            let line = i + 1;
+            let mut data = l.split_whitespace();
+            // Colors are RGB numbers, else anything str2col parses (without spaces)
+            let col = match data.next() {
+                Some(col) => col,
+                None => continue,
+            };
+            let col = match col.parse::<u32>() {
+                Ok(rgb) => graphics::Color::from_rgb_u32(rgb),
+                Err(_) => str2col(col).map_err(|e| SceneError::Color(line, e))?,
+            };
             let mut coords = data
-                .iter()
-                .map(|tok| {
-                    let p: Vec<f32> = tok.split(',').map(|v| v.parse::<f32>().unwrap()).collect();
-                    na::Point2::new(p[0], p[1])
-                })
-                .collect::<Vec<_>>();
+                .map(|tok| parse_point(tok).ok_or_else(|| SceneError::Point(line, tok.to_owned())))
+                .collect::<Result<Vec<_>, _>>()?;
+            if coords.len() % 3 != 0 {
+                return Err(SceneError::Triangles(line, coords.len()));
+            }
             // Find index of color into colors
             if let Some((c, _)) = ct.colors.iter().enumerate().find(|(_, c)| **c == col) {
                 // Add triangles to color
@@ -157,18 +488,22 @@ impl From<&str> for ColoredTriangles {
                 ct.colors.push(col);
                 ct.triangles.push(coords);
             }
-        });
-        ct
+        }
+        Ok(ct)
     }
 }
 
 impl fmt::Display for ColoredTriangles {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for i in 0..self.colors.len() {
+            let color = match self.colors[i].to_rgba() {
+                (_, _, _, 255) => self.colors[i].to_rgb_u32().to_string(),
+                _ => col2str(self.colors[i]),
+            };
             writeln!(
                 f,
                 "{} {}",
-                self.colors[i].to_rgb_u32(),
+                color,
                 self.triangles[i]
                     .iter()
                     .map(|p| format!("{},{}", p.x, p.y))
@@ -180,9 +515,732 @@ impl fmt::Display for ColoredTriangles {
     }
 }
 
+/// True if point lies inside the triangle p1, p2, p3 (or on its border)
+fn triangle_contains(
+    p1: &na::Point2<f32>,
+    p2: &na::Point2<f32>,
+    p3: &na::Point2<f32>,
+    point: &na::Point2<f32>,
+) -> bool {
+    // https://stackoverflow.com/questions/2049582/how-to-determine-if-a-point-is-in-a-2d-triangle
+    fn sign(p1: &na::Point2<f32>, p2: &na::Point2<f32>, p3: &na::Point2<f32>) -> f32 {
+        (p1.x - p3.x) * (p2.y - p3.y) - (p2.x - p3.x) * (p1.y - p3.y)
+    }
+
+    let d1 = sign(point, p1, p2);
+    let d2 = sign(point, p2, p3);
+    let d3 = sign(point, p3, p1);
+    let has_neg = (d1 < 0.0) || (d2 < 0.0) || (d3 < 0.0);
+    let has_pos = (d1 > 0.0) || (d2 > 0.0) || (d3 > 0.0);
+    !(has_neg && has_pos)
+}
+
+/// Point of the segment a-b closest to p
+fn closest_on_segment(
+    a: &na::Point2<f32>,
+    b: &na::Point2<f32>,
+    p: &na::Point2<f32>,
+) -> na::Point2<f32> {
+    let ab = b - a;
+    let len2 = ab.norm_squared();
+    if len2 == 0.0 {
+        return *a;
+    }
+    let t = ((p - a).dot(&ab) / len2).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+/// Twice the signed area of the triangle p1, p2, p3, zero if they are aligned
+fn cross(p1: &na::Point2<f32>, p2: &na::Point2<f32>, p3: &na::Point2<f32>) -> f32 {
+    (p2.x - p1.x) * (p3.y - p1.y) - (p2.y - p1.y) * (p3.x - p1.x)
+}
+
+/// True if segments a1-a2 and b1-b2 have some point in common
+fn segments_intersect(
+    a1: &na::Point2<f32>,
+    a2: &na::Point2<f32>,
+    b1: &na::Point2<f32>,
+    b2: &na::Point2<f32>,
+) -> bool {
+    // Aligned point r lying between p and q
+    fn between(p: &na::Point2<f32>, q: &na::Point2<f32>, r: &na::Point2<f32>) -> bool {
+        r.x >= p.x.min(q.x) && r.x <= p.x.max(q.x) && r.y >= p.y.min(q.y) && r.y <= p.y.max(q.y)
+    }
+
+    let d1 = cross(b1, b2, a1);
+    let d2 = cross(b1, b2, a2);
+    let d3 = cross(a1, a2, b1);
+    let d4 = cross(a1, a2, b2);
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+    (d1 == 0.0 && between(b1, b2, a1))
+        || (d2 == 0.0 && between(b1, b2, a2))
+        || (d3 == 0.0 && between(a1, a2, b1))
+        || (d4 == 0.0 && between(a1, a2, b2))
+}
+
+/// Splits a polygon into triangles by ear clipping, returning the indices of
+/// their vertices in polygon. Repeated and aligned points are skipped. None if
+/// the polygon is not simple (i.e. its edges cross) or has no area
+pub fn triangulate(polygon: &[na::Point2<f32>]) -> Option<Vec<[usize; 3]>> {
+    // Corner k of the polygon made by the points at indices idx
+    fn corner(idx: &[usize], k: usize) -> (usize, usize, usize) {
+        let n = idx.len();
+        (idx[(k + n - 1) % n], idx[k], idx[(k + 1) % n])
+    }
+    let aligned = |idx: &[usize], k: usize| {
+        let (i0, i1, i2) = corner(idx, k);
+        cross(&polygon[i0], &polygon[i1], &polygon[i2]) == 0.0
+    };
+
+    let mut idx = (0..polygon.len()).collect::<Vec<_>>();
+    while let Some(k) = (0..idx.len()).find(|&k| aligned(&idx, k)) {
+        idx.remove(k);
+    }
+    let n = idx.len();
+    if n < 3 {
+        return None;
+    }
+    // Edges not sharing a point must not cross
+    for a in 0..n {
+        for b in a + 2..n {
+            if a == 0 && b == n - 1 {
+                continue;
+            }
+            let (a1, a2) = (&polygon[idx[a]], &polygon[idx[a + 1]]);
+            let (b1, b2) = (&polygon[idx[b]], &polygon[idx[(b + 1) % n]]);
+            if segments_intersect(a1, a2, b1, b2) {
+                return None;
+            }
+        }
+    }
+    // Positive if points go counterclockwise (with y going up)
+    let area = (0..n).fold(0.0, |acc, k| {
+        let (p, q) = (&polygon[idx[k]], &polygon[idx[(k + 1) % n]]);
+        acc + p.x * q.y - q.x * p.y
+    });
+
+    let mut tris = vec![];
+    while idx.len() > 3 {
+        // An ear is a convex corner with no other point inside it
+        let ear = (0..idx.len()).find(|&k| {
+            let (i0, i1, i2) = corner(&idx, k);
+            let (a, b, c) = (&polygon[i0], &polygon[i1], &polygon[i2]);
+            cross(a, b, c) * area > 0.0
+                && idx
+                    .iter()
+                    .filter(|&&j| j != i0 && j != i1 && j != i2)
+                    .all(|&j| !triangle_contains(a, b, c, &polygon[j]))
+        });
+        match ear {
+            Some(k) => {
+                let (i0, i1, i2) = corner(&idx, k);
+                tris.push([i0, i1, i2]);
+                idx.remove(k);
+            }
+            // Clipping can leave aligned points, that have no area to fill
+            None => {
+                let k = (0..idx.len()).find(|&k| aligned(&idx, k))?;
+                idx.remove(k);
+            }
+        }
+    }
+    if !aligned(&idx, 0) {
+        tris.push([idx[0], idx[1], idx[2]]);
+    }
+    Some(tris)
+}
+
+/// Triangles of a fan approximating the ellipse with given center and radii,
+/// having a rim of segments points
+pub fn ellipse_fan(
+    center: &na::Point2<f32>,
+    radii: &na::Vector2<f32>,
+    segments: usize,
+) -> Vec<[na::Point2<f32>; 3]> {
+    let rim = (0..segments)
+        .map(|k| {
+            let a = 2.0 * std::f32::consts::PI * k as f32 / segments as f32;
+            center + na::Vector2::new(radii.x * a.cos(), radii.y * a.sin())
+        })
+        .collect::<Vec<_>>();
+    (0..segments)
+        .map(|k| [*center, rim[k], rim[(k + 1) % segments]])
+        .collect()
+}
+
+/// Key to compare points exactly, e.g. in sets
+fn point_key(p: &na::Point2<f32>) -> (u32, u32) {
+    (p.x.to_bits(), p.y.to_bits())
+}
+
+/// Edges of triangles, given as consecutive triples of points, each edge once
+/// even when shared by two triangles. Edges with equal ends are skipped
+pub fn triangle_edges(points: &[na::Point2<f32>]) -> Vec<[na::Point2<f32>; 2]> {
+    let mut seen = HashSet::new();
+    let mut edges = vec![];
+    for t in points.chunks_exact(3) {
+        for k in 0..3 {
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            let (ka, kb) = (point_key(&a), point_key(&b));
+            if ka != kb && seen.insert((ka.min(kb), ka.max(kb))) {
+                edges.push([a, b]);
+            }
+        }
+    }
+    edges
+}
+
+/// Segments between consecutive points, and back to the first one if closed
+pub fn polyline_edges(points: &[na::Point2<f32>], closed: bool) -> Vec<[na::Point2<f32>; 2]> {
+    let mut edges: Vec<_> = points.windows(2).map(|w| [w[0], w[1]]).collect();
+    if closed && points.len() > 2 {
+        edges.push([points[points.len() - 1], points[0]]);
+    }
+    edges
+}
+
+/// Triangles covering segments with lines of given width, two for each segment.
+/// Lines are longer than segments by half the width on both ends, so lines
+/// meeting at an angle leave no gaps; segments with no length are skipped
+pub fn stroke(segments: &[[na::Point2<f32>; 2]], width: f32) -> Vec<na::Point2<f32>> {
+    let mut tris = Vec::with_capacity(segments.len() * 6);
+    for [a, b] in segments {
+        let len = na::distance(a, b);
+        if len <= 0.0 {
+            continue;
+        }
+        // Half width along the segment, and across it
+        let along = (b - a) * (0.5 * width / len);
+        let across = na::Vector2::new(-along.y, along.x);
+        let (a, b) = (a - along, b + along);
+        tris.extend_from_slice(&[
+            a + across,
+            b + across,
+            b - across,
+            a + across,
+            b - across,
+            a - across,
+        ]);
+    }
+    tris
+}
+
+/// Triangles drawing a square of given side centered on each point, once for
+/// repeated points
+pub fn markers(points: &[na::Point2<f32>], side: f32) -> Vec<na::Point2<f32>> {
+    let mut seen = HashSet::new();
+    let h = side * 0.5;
+    let mut tris = vec![];
+    for p in points.iter().filter(|p| seen.insert(point_key(p))) {
+        let (tl, br) = (p + na::Vector2::new(-h, -h), p + na::Vector2::new(h, h));
+        let (tr, bl) = (p + na::Vector2::new(h, -h), p + na::Vector2::new(-h, h));
+        tris.extend_from_slice(&[tl, tr, br, tl, br, bl]);
+    }
+    tris
+}
+
+/// Triangles sharing a pool of vertices, so that moving a vertex moves every
+/// triangle using it. Vertices and triangles are kept in a spatial index, so
+/// they should be changed only through methods
+#[derive(Clone, Debug)]
+pub struct IndexedTriangles {
+    /// Colors that can be used to draw triangles
+    pub colors: Vec<graphics::Color>,
+    /// Vertices shared by the triangles of every color
+    pub vertices: Vec<na::Point2<f32>>,
+    /// Indices of the vertices of triangles, one list for each color
+    pub triangles: Vec<Vec<[usize; 3]>>,
+    /// Indices of vertices, by position
+    vertex_grid: Grid<usize>,
+    /// Indices (color, triangle) of triangles, by bounding box
+    triangle_grid: Grid<(usize, usize)>,
+}
+
+impl IndexedTriangles {
+    /// Index of the vertex exactly at point, added to the pool if missing
+    pub fn vertex(&mut self, point: &na::Point2<f32>) -> usize {
+        let found = self
+            .vertex_grid
+            .at(point)
+            .iter()
+            .cloned()
+            .find(|&v| self.vertices[v] == *point);
+        match found {
+            Some(i) => i,
+            None => {
+                self.vertices.push(*point);
+                self.index_vertices_from(self.vertices.len() - 1);
+                self.vertices.len() - 1
+            }
+        }
+    }
+
+    /// Vertices of the ith triangle for color index c
+    pub fn triangle(&self, c: usize, i: usize) -> [na::Point2<f32>; 3] {
+        let t = self.triangles[c][i];
+        [
+            self.vertices[t[0]],
+            self.vertices[t[1]],
+            self.vertices[t[2]],
+        ]
+    }
+
+    /// Vertices of every triangle for color index c, three for each triangle
+    pub fn points(&self, c: usize) -> Vec<na::Point2<f32>> {
+        self.triangles[c]
+            .iter()
+            .flat_map(|t| t.iter().map(move |&v| self.vertices[v]))
+            .collect()
+    }
+
+    /// Corners of the bounding box of the ith triangle for color index c
+    fn bounds(&self, c: usize, i: usize) -> (na::Point2<f32>, na::Point2<f32>) {
+        let [p1, p2, p3] = self.triangle(c, i);
+        (
+            na::Point2::new(p1.x.min(p2.x).min(p3.x), p1.y.min(p2.y).min(p3.y)),
+            na::Point2::new(p1.x.max(p2.x).max(p3.x), p1.y.max(p2.y).max(p3.y)),
+        )
+    }
+
+    /// Adds to the index the triangles of color index c, from index first on
+    fn index_triangles_from(&mut self, c: usize, first: usize) {
+        for i in first..self.triangles[c].len() {
+            let (min, max) = self.bounds(c, i);
+            self.triangle_grid.insert((c, i), &min, &max);
+        }
+    }
+
+    /// Adds to the index the vertices from index first on
+    fn index_vertices_from(&mut self, first: usize) {
+        for (v, p) in self.vertices.iter().enumerate().skip(first) {
+            self.vertex_grid.insert(v, p, p);
+        }
+    }
+
+    /// Builds the index from scratch, with cells as big as the average triangle
+    fn rebuild_index(&mut self) {
+        let (sum, n) = (0..self.triangles.len())
+            .flat_map(|c| (0..self.triangles[c].len()).map(move |i| (c, i)))
+            .map(|(c, i)| {
+                let (min, max) = self.bounds(c, i);
+                (max.x - min.x).max(max.y - min.y)
+            })
+            .fold((0.0, 0), |(sum, n), size| (sum + size, n + 1));
+        let size = if n > 0 && sum > 0.0 {
+            sum / n as f32
+        } else {
+            DEFAULT_CELL
+        };
+        self.vertex_grid.reset(size);
+        self.triangle_grid.reset(size);
+        self.index_vertices_from(0);
+        for c in 0..self.triangles.len() {
+            self.index_triangles_from(c, 0);
+        }
+    }
+
+    /// Indices of triangles using some vertices
+    fn triangles_using(&self, vs: &[usize]) -> Vec<(usize, usize)> {
+        // A triangle always covers the cells of its vertices
+        let mut tris = vs
+            .iter()
+            .flat_map(|&v| {
+                self.triangle_grid
+                    .at(&self.vertices[v])
+                    .iter()
+                    .cloned()
+                    .filter(move |&(c, i)| self.triangles[c][i].contains(&v))
+            })
+            .collect::<Vec<_>>();
+        tris.sort_unstable();
+        tris.dedup();
+        tris
+    }
+
+    /// Appends a triangle to color index c, returning its index. Vertices
+    /// already in the pool at the same position are shared
+    pub fn add_triangle(&mut self, c: usize, tri: &[na::Point2<f32>]) -> usize {
+        let t = [
+            self.vertex(&tri[0]),
+            self.vertex(&tri[1]),
+            self.vertex(&tri[2]),
+        ];
+        self.triangles[c].push(t);
+        self.index_triangles_from(c, self.triangles[c].len() - 1);
+        self.triangles[c].len() - 1
+    }
+
+    /// Removes several triangles, returning their vertex indices
+    fn take_triangles(&mut self, sel: &[(usize, usize)]) -> Vec<[usize; 3]> {
+        let mut sel = sel.to_vec();
+        sel.sort_unstable();
+        sel.dedup();
+        // Triangles following a removed one shift back by the number of
+        // removed ones before them
+        self.triangle_grid
+            .retain_map(|(c, i)| match sel.binary_search(&(c, i)) {
+                Ok(_) => None,
+                Err(k) => Some((c, i - (k - sel.partition_point(|&(sc, _)| sc < c)))),
+            });
+        let mut taken = sel
+            .iter()
+            .rev()
+            .map(|&(c, i)| self.triangles[c].remove(i))
+            .collect::<Vec<_>>();
+        taken.reverse();
+        taken
+    }
+
+    /// Removes several triangles, given as (color index, triangle index) pairs
+    pub fn remove_triangles(&mut self, sel: &[(usize, usize)]) {
+        self.take_triangles(sel);
+        self.remove_unused_vertices();
+    }
+
+    /// Drops vertices not used by any triangle, updating indices
+    fn remove_unused_vertices(&mut self) {
+        let mut used = vec![false; self.vertices.len()];
+        for t in self.triangles.iter().flatten() {
+            for &v in t {
+                used[v] = true;
+            }
+        }
+        if used.iter().all(|&u| u) {
+            return;
+        }
+        let mut remap = vec![0; self.vertices.len()];
+        let mut n = 0;
+        for (i, &u) in used.iter().enumerate() {
+            if u {
+                self.vertices[n] = self.vertices[i];
+                remap[i] = n;
+                n += 1;
+            }
+        }
+        self.vertices.truncate(n);
+        for t in self.triangles.iter_mut().flatten() {
+            for v in t.iter_mut() {
+                *v = remap[*v];
+            }
+        }
+        self.vertex_grid
+            .retain_map(|v| if used[v] { Some(remap[v]) } else { None });
+    }
+
+    /// Adds a color with no triangles, on top of the others, returning its index
+    pub fn add_color(&mut self, color: graphics::Color) -> usize {
+        self.colors.push(color);
+        self.triangles.push(vec![]);
+        self.colors.len() - 1
+    }
+
+    /// Removes color index c along with its triangles
+    pub fn remove_color(&mut self, c: usize) {
+        self.colors.remove(c);
+        self.triangles.remove(c);
+        // Triangles of following colors change index
+        self.triangle_grid.retain_map(|(tc, i)| {
+            if tc == c {
+                None
+            } else if tc > c {
+                Some((tc - 1, i))
+            } else {
+                Some((tc, i))
+            }
+        });
+        self.remove_unused_vertices();
+    }
+
+    /// Swaps colors index a and b with their triangles, changing which are on top
+    pub fn swap_colors(&mut self, a: usize, b: usize) {
+        self.colors.swap(a, b);
+        self.triangles.swap(a, b);
+        self.triangle_grid.retain_map(|(c, i)| {
+            if c == a {
+                Some((b, i))
+            } else if c == b {
+                Some((a, i))
+            } else {
+                Some((c, i))
+            }
+        });
+    }
+
+    /// Moves triangles to color index c, placing them on top of it, and returns their new indices
+    pub fn recolor(&mut self, sel: &[(usize, usize)], c: usize) -> Vec<(usize, usize)> {
+        let tris = self.take_triangles(sel);
+        let first = self.triangles[c].len();
+        self.triangles[c].extend(tris);
+        self.index_triangles_from(c, first);
+        (first..self.triangles[c].len()).map(|i| (c, i)).collect()
+    }
+
+    /// Gives triangles their own copy of the vertices they share with other triangles
+    pub fn detach(&mut self, sel: &[(usize, usize)]) {
+        let sel: HashSet<(usize, usize)> = sel.iter().cloned().collect();
+        // Vertices used by triangles that are not being detached
+        let mut shared = vec![false; self.vertices.len()];
+        for (c, tris) in self.triangles.iter().enumerate() {
+            for (i, t) in tris.iter().enumerate() {
+                if !sel.contains(&(c, i)) {
+                    for &v in t {
+                        shared[v] = true;
+                    }
+                }
+            }
+        }
+        let first = self.vertices.len();
+        let mut copies = HashMap::new();
+        for &(c, i) in &sel {
+            for v in self.triangles[c][i].iter_mut() {
+                if shared[*v] {
+                    let len = self.vertices.len();
+                    let copy = *copies.entry(*v).or_insert(len);
+                    if copy == len {
+                        self.vertices.push(self.vertices[*v]);
+                    }
+                    *v = copy;
+                }
+            }
+        }
+        // Copies are at the same position, triangles didn't move
+        self.index_vertices_from(first);
+    }
+
+    /// Sorted indices of the vertices used by some triangles
+    pub fn vertices_of(&self, sel: &[(usize, usize)]) -> Vec<usize> {
+        let mut vs = sel
+            .iter()
+            .flat_map(|&(c, i)| self.triangles[c][i].iter().cloned())
+            .collect::<Vec<_>>();
+        vs.sort_unstable();
+        vs.dedup();
+        vs
+    }
+
+    /// Moves vertices, given as (vertex index, new position) pairs
+    pub fn move_vertices(&mut self, moves: &[(usize, na::Point2<f32>)]) {
+        let vs = moves.iter().map(|&(v, _)| v).collect::<Vec<_>>();
+        let tris = self.triangles_using(&vs);
+        for &(c, i) in &tris {
+            let (min, max) = self.bounds(c, i);
+            self.triangle_grid.remove((c, i), &min, &max);
+        }
+        for &(v, p) in moves {
+            let old = self.vertices[v];
+            self.vertex_grid.remove(v, &old, &old);
+            self.vertices[v] = p;
+            self.vertex_grid.insert(v, &p, &p);
+        }
+        for &(c, i) in &tris {
+            let (min, max) = self.bounds(c, i);
+            self.triangle_grid.insert((c, i), &min, &max);
+        }
+    }
+
+    /// In place translation of some vertices, adding to each an offset
+    pub fn translate_vertices(&mut self, vs: &[usize], offset: &na::Vector2<f32>) {
+        let moves = vs
+            .iter()
+            .map(|&v| (v, self.vertices[v] + offset))
+            .collect::<Vec<_>>();
+        self.move_vertices(&moves);
+    }
+
+    /// Replaces vertex from with vertex into, removing triangles that collapsed.
+    /// Returns true if some triangle was removed
+    pub fn weld(&mut self, from: usize, into: usize) -> bool {
+        let p = self.vertices[into];
+        self.move_vertices(&[(from, p)]);
+        let mut collapsed = vec![];
+        for (c, i) in self.triangles_using(&[from]) {
+            let t = &mut self.triangles[c][i];
+            for v in t.iter_mut() {
+                if *v == from {
+                    *v = into;
+                }
+            }
+            if t[0] == t[1] || t[1] == t[2] || t[2] == t[0] {
+                collapsed.push((c, i));
+            }
+        }
+        self.remove_triangles(&collapsed);
+        !collapsed.is_empty()
+    }
+
+    /// Indices of the n nearest vertices and their (squared) distances from point
+    pub fn nearest(&self, n: usize, point: &na::Point2<f32>) -> Vec<(usize, f32)> {
+        let center = self.vertex_grid.cell_of(point);
+        let mut found: Vec<(usize, f32)> = vec![];
+        let mut r = 0;
+        loop {
+            // When rings would visit many empty cells, a full scan is faster
+            let side = 2 * r as usize + 1;
+            if side * side > 4 * self.vertex_grid.occupied() {
+                found = self
+                    .vertices
+                    .iter()
+                    .map(|p| na::distance_squared(p, point))
+                    .enumerate()
+                    .collect();
+                break;
+            }
+            found.extend(
+                self.vertex_grid
+                    .ring(center, r)
+                    .into_iter()
+                    .map(|v| (v, na::distance_squared(&self.vertices[v], point))),
+            );
+            // Vertices closer than r cells have all been found
+            let reach = r as f32 * self.vertex_grid.size();
+            if found.iter().filter(|(_, d)| *d <= reach * reach).count() >= n {
+                break;
+            }
+            r += 1;
+        }
+        // Sort distances
+        found
+            .as_mut_slice()
+            .sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        found.truncate(n);
+        found
+    }
+
+    /// Closest point to point on the edges of triangles, if within radius.
+    /// Edges having a vertex in skip are ignored
+    pub fn nearest_on_edge(
+        &self,
+        point: &na::Point2<f32>,
+        radius: f32,
+        skip: &[usize],
+    ) -> Option<na::Point2<f32>> {
+        let r = na::Vector2::new(radius, radius);
+        let mut tris = self.triangle_grid.within(&(point - r), &(point + r));
+        tris.sort_unstable();
+        tris.dedup();
+        tris.iter()
+            .flat_map(|&(c, i)| {
+                let t = self.triangles[c][i];
+                vec![(t[0], t[1]), (t[1], t[2]), (t[2], t[0])]
+            })
+            .filter(|(a, b)| !skip.contains(a) && !skip.contains(b))
+            .map(|(a, b)| closest_on_segment(&self.vertices[a], &self.vertices[b], point))
+            .map(|q| (q, na::distance_squared(&q, point)))
+            .filter(|&(_, sqd)| sqd <= radius * radius)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(q, _)| q)
+    }
+
+    /// Indices of triangles containing the point, one at a time
+    fn hits_iter<'a>(
+        &'a self,
+        point: &'a na::Point2<f32>,
+    ) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.triangle_grid
+            .at(point)
+            .iter()
+            .cloned()
+            .filter(move |&(c, i)| {
+                let [p1, p2, p3] = self.triangle(c, i);
+                triangle_contains(&p1, &p2, &p3, point)
+            })
+    }
+
+    /// Indices of every triangle containing the point, topmost first. Colors
+    /// are painted in order and so are triangles of a color, so a triangle is
+    /// above those with lower (color index, triangle index)
+    pub fn hits(&self, point: &na::Point2<f32>) -> Vec<(usize, usize)> {
+        let mut hits = self.hits_iter(point).collect::<Vec<_>>();
+        hits.sort_unstable_by(|a, b| b.cmp(a));
+        hits
+    }
+
+    /// Returns indices of the visible (i.e. topmost) triangle containing the point, if any
+    pub fn colliding(&self, point: &na::Point2<f32>) -> Option<(usize, usize)> {
+        self.hits_iter(point).max()
+    }
+
+    /// Indices of triangles lying completely inside the rectangle having corners a and b
+    pub fn inside(&self, a: &na::Point2<f32>, b: &na::Point2<f32>) -> Vec<(usize, usize)> {
+        let min = na::Point2::new(a.x.min(b.x), a.y.min(b.y));
+        let max = na::Point2::new(a.x.max(b.x), a.y.max(b.y));
+        let within = |&v: &usize| {
+            let p = self.vertices[v];
+            p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y
+        };
+        let mut tris = self
+            .triangle_grid
+            .within(&min, &max)
+            .into_iter()
+            .filter(|&(c, i)| self.triangles[c][i].iter().all(within))
+            .collect::<Vec<_>>();
+        tris.sort_unstable();
+        tris.dedup();
+        tris
+    }
+
+    /// In place scale of vertices, multiplying each axis by a scale factor
+    pub fn scale(&mut self, x_scale: f32, y_scale: f32) {
+        for p in self.vertices.iter_mut() {
+            p.x *= x_scale;
+            p.y *= y_scale;
+        }
+        self.rebuild_index();
+    }
+
+    /// In place translation of vertices, adding to each axis an offset
+    pub fn translate(&mut self, x_off: f32, y_off: f32) {
+        for p in self.vertices.iter_mut() {
+            p.x += x_off;
+            p.y += y_off;
+        }
+        self.rebuild_index();
+    }
+}
+
+/// Shares vertices of triangles that are at the very same position
+impl From<&ColoredTriangles> for IndexedTriangles {
+    fn from(ct: &ColoredTriangles) -> Self {
+        let mut it = IndexedTriangles {
+            colors: ct.colors.clone(),
+            vertices: vec![],
+            triangles: vec![vec![]; ct.colors.len()],
+            vertex_grid: Grid::new(DEFAULT_CELL),
+            triangle_grid: Grid::new(DEFAULT_CELL),
+        };
+        let mut index = HashMap::new();
+        for (c, points) in ct.triangles.iter().enumerate() {
+            for tri in points.chunks_exact(3) {
+                let mut t = [0; 3];
+                for (v, p) in t.iter_mut().zip(tri) {
+                    let len = it.vertices.len();
+                    *v = *index.entry((p.x.to_bits(), p.y.to_bits())).or_insert(len);
+                    if *v == len {
+                        it.vertices.push(*p);
+                    }
+                }
+                it.triangles[c].push(t);
+            }
+        }
+        it.rebuild_index();
+        it
+    }
+}
+
+impl From<&IndexedTriangles> for ColoredTriangles {
+    fn from(it: &IndexedTriangles) -> Self {
+        ColoredTriangles {
+            colors: it.colors.clone(),
+            triangles: (0..it.colors.len()).map(|c| it.points(c)).collect(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ColoredTriangles;
+    use super::{ColoredTriangles, IndexedTriangles};
     use ggez::graphics;
     use ggez::nalgebra as na;
 
@@ -201,7 +1259,7 @@ mod tests {
 
     #[test]
     fn colored_triangles_from_string() {
-        let s: ColoredTriangles = "255 0.0,0.0 1.0,0.0 1.0,1.0\n".into();
+        let s: ColoredTriangles = "255 0.0,0.0 1.0,0.0 1.0,1.0\n".parse().unwrap();
         assert_eq!(
             s,
             ColoredTriangles {
@@ -214,4 +1272,420 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn colored_triangles_with_alpha() {
+        let s = "255 0,0 1,0 1,1\n#ff000080 0,0 1,1 0,1\n";
+        let ct: ColoredTriangles = s.parse().unwrap();
+        assert_eq!(ct.colors[1], graphics::Color::from_rgba(255, 0, 0, 128));
+        assert_eq!(ct.to_string(), s);
+        assert_eq!(super::col2str(ct.colors[0]), "#0000ff");
+    }
+
+    #[test]
+    fn colored_triangles_errors() {
+        use super::{ColorError, SceneError};
+        let parse = |s: &str| s.parse::<ColoredTriangles>();
+        // Blank lines are skipped
+        assert_eq!(
+            parse("\n255 0,0 1,0 1,1\n\n").unwrap().triangles[0].len(),
+            3
+        );
+        assert_eq!(
+            parse("255 0,0 1,0 1,1\nredish 0,0 1,0 1,1"),
+            Err(SceneError::Color(
+                2,
+                ColorError::UnknownName("redish".to_owned())
+            ))
+        );
+        assert_eq!(
+            parse("255 0,0 1,x 1,1"),
+            Err(SceneError::Point(1, "1,x".to_owned()))
+        );
+        assert_eq!(
+            parse("255 0,0 1 1,1"),
+            Err(SceneError::Point(1, "1".to_owned()))
+        );
+        assert_eq!(
+            parse("255 0,0 1,0,2 1,1"),
+            Err(SceneError::Point(1, "1,0,2".to_owned()))
+        );
+        let err = parse("\n255 0,0 1,0 1,1 2,2").unwrap_err();
+        assert_eq!(err, SceneError::Triangles(2, 4));
+        assert_eq!(
+            err.to_string(),
+            "line 2: 4 points are not a whole number of triangles"
+        );
+    }
+
+    #[test]
+    fn colors_from_string() {
+        use super::{str2col, ColorError};
+        let orange = graphics::Color::from_rgb(255, 136, 0);
+        assert_eq!(str2col("#ff8800"), Ok(orange));
+        assert_eq!(str2col("#F80"), Ok(orange));
+        assert_eq!(str2col("rgb(255, 136, 0)"), Ok(orange));
+        assert_eq!(str2col("RGB(100%,136,0)"), Ok(orange));
+        assert_eq!(
+            str2col(" DarkOrange "),
+            Ok(graphics::Color::from_rgb(255, 140, 0))
+        );
+        let translucent = graphics::Color::from_rgba(255, 0, 0, 128);
+        assert_eq!(str2col("#ff000080"), Ok(translucent));
+        assert_eq!(
+            str2col("#f008"),
+            Ok(graphics::Color::from_rgba(255, 0, 0, 136))
+        );
+        assert_eq!(str2col("rgba(255,0,0,0.5)").map(|c| c.a), Ok(0.5));
+        assert_eq!(
+            str2col("#ff00"),
+            Ok(graphics::Color::from_rgba(255, 255, 0, 0))
+        );
+        assert_eq!(str2col("#ff000"), Err(ColorError::HexLength(5)));
+        assert_eq!(str2col("#ffgg00"), Err(ColorError::HexDigit('g')));
+        assert_eq!(str2col("rgb(1, 2)"), Err(ColorError::Arguments(2)));
+        assert_eq!(
+            str2col("rgb(1, 2, 300)"),
+            Err(ColorError::Component("300".to_owned()))
+        );
+        assert_eq!(
+            str2col("rgba(1, 2, 3, x)"),
+            Err(ColorError::Component("x".to_owned()))
+        );
+        assert_eq!(
+            str2col("redish"),
+            Err(ColorError::UnknownName("redish".to_owned()))
+        );
+        // Scenes can use any color without spaces
+        let ct: ColoredTriangles = "red 0,0 1,0 1,1\n#00f 0,0 1,1 0,1\n".parse().unwrap();
+        assert_eq!(
+            ct.colors,
+            vec![
+                graphics::Color::from_rgb(255, 0, 0),
+                graphics::Color::from_rgb(0, 0, 255)
+            ]
+        );
+    }
+
+    #[test]
+    fn indexed_triangles_share_vertices() {
+        let ct: ColoredTriangles = "255 0,0 1,0 1,1\n65280 0,0 1,1 0,1\n".parse().unwrap();
+        let it = IndexedTriangles::from(&ct);
+        assert_eq!(it.vertices.len(), 4);
+        assert_eq!(it.triangles, vec![vec![[0, 1, 2]], vec![[0, 2, 3]]]);
+        assert_eq!(ColoredTriangles::from(&it), ct);
+    }
+
+    #[test]
+    fn indexed_triangles_editing() {
+        let ct: ColoredTriangles = "255 0,0 1,0 1,1 5,5 6,5 6,6\n65280 0,0 1,1 0,1\n"
+            .parse()
+            .unwrap();
+        let mut it = IndexedTriangles::from(&ct);
+        // Moving a shared vertex moves every triangle using it
+        it.translate_vertices(&[0], &na::Vector2::new(-1.0, 0.0));
+        assert_eq!(it.triangle(0, 0)[0], na::Point2::new(-1.0, 0.0));
+        assert_eq!(it.triangle(1, 0)[0], na::Point2::new(-1.0, 0.0));
+        // Only the first triangles of each color fit in the rectangle
+        assert_eq!(
+            it.inside(&na::Point2::new(2.0, 2.0), &na::Point2::new(-1.0, -1.0)),
+            vec![(0, 0), (1, 0)]
+        );
+        // Detached triangles no longer share vertices with others
+        it.detach(&[(1, 0)]);
+        assert_eq!(it.vertices.len(), 9);
+        assert_eq!(it.vertices_of(&[(1, 0)]), vec![6, 7, 8]);
+        // Moving to another color puts the triangle on top of that color
+        assert_eq!(it.recolor(&[(0, 1)], 1), vec![(1, 1)]);
+        assert_eq!(it.triangle(1, 1)[0], na::Point2::new(5.0, 5.0));
+        // Removing triangles drops their vertices
+        it.remove_triangles(&[(1, 0), (1, 1)]);
+        assert_eq!(it.vertices.len(), 3);
+        // Welding two vertices of a triangle collapses it
+        assert!(it.weld(1, 0));
+        assert!(it.triangles[0].is_empty());
+        assert!(it.vertices.is_empty());
+    }
+
+    #[test]
+    fn indexed_triangles_layers() {
+        let ct: ColoredTriangles = "255 0,0 4,0 0,4\n65280 0,0 2,0 0,2\n".parse().unwrap();
+        let mut it = IndexedTriangles::from(&ct);
+        let p = na::Point2::new(1.0, 1.0);
+        assert_eq!(it.add_color(graphics::WHITE), 2);
+        assert_eq!(
+            it.add_triangle(
+                2,
+                &[p, na::Point2::new(3.0, 1.0), na::Point2::new(1.0, 3.0)]
+            ),
+            0
+        );
+        assert_eq!(it.hits(&p), vec![(2, 0), (1, 0), (0, 0)]);
+        // Swapping colors changes which triangles are on top
+        it.swap_colors(0, 2);
+        assert_eq!(it.colors[0], graphics::WHITE);
+        assert_eq!(it.hits(&p), vec![(2, 0), (1, 0), (0, 0)]);
+        assert_eq!(it.triangle(2, 0)[1], na::Point2::new(4.0, 0.0));
+        // Removing a color drops its triangles and unused vertices
+        it.remove_color(1);
+        assert_eq!(it.colors.len(), 2);
+        assert_eq!(it.hits(&p), vec![(1, 0), (0, 0)]);
+        assert_eq!(it.vertices.len(), 6);
+    }
+
+    #[test]
+    fn indexed_triangles_nearest_on_edge() {
+        let ct: ColoredTriangles = "255 0,0 10,0 0,10\n".parse().unwrap();
+        let it = IndexedTriangles::from(&ct);
+        let near = |x, y, skip: &[usize]| it.nearest_on_edge(&na::Point2::new(x, y), 2.0, skip);
+        assert_eq!(near(4.0, -1.0, &[]), Some(na::Point2::new(4.0, 0.0)));
+        assert_eq!(near(6.0, 6.0, &[]), Some(na::Point2::new(5.0, 5.0)));
+        // Past the end of an edge, its vertex is the closest point
+        assert_eq!(near(-1.0, -1.0, &[]), Some(na::Point2::new(0.0, 0.0)));
+        assert_eq!(near(4.0, -3.0, &[]), None);
+        // Edges of skipped vertices don't count
+        assert_eq!(near(4.0, -1.0, &[0]), None);
+        assert_eq!(near(1.0, 4.0, &[1]), Some(na::Point2::new(0.0, 4.0)));
+    }
+
+    #[test]
+    fn polygons_are_triangulated() {
+        let pts = |v: &[(f32, f32)]| {
+            v.iter()
+                .map(|&(x, y)| na::Point2::new(x, y))
+                .collect::<Vec<_>>()
+        };
+        let area = |poly: &[na::Point2<f32>], tris: &[[usize; 3]]| {
+            tris.iter()
+                .map(|t| super::cross(&poly[t[0]], &poly[t[1]], &poly[t[2]]).abs() / 2.0)
+                .sum::<f32>()
+        };
+        // A concave L shape, with a repeated and an aligned point
+        let l = pts(&[
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+            (0.0, 1.0),
+        ]);
+        let tris = super::triangulate(&l).unwrap();
+        assert_eq!(tris.len(), 4);
+        assert_eq!(area(&l, &tris), 3.0);
+        // Same shape, the other way around
+        let mut r = l.clone();
+        r.reverse();
+        assert_eq!(area(&r, &super::triangulate(&r).unwrap()), 3.0);
+        // Crossing edges and no area at all
+        assert!(
+            super::triangulate(&pts(&[(0.0, 0.0), (1.0, 1.0), (1.0, 0.0), (0.0, 1.0)])).is_none()
+        );
+        assert!(super::triangulate(&pts(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0)])).is_none());
+    }
+
+    #[test]
+    fn ellipse_fan_lies_on_ellipse() {
+        let center = na::Point2::new(10.0, 10.0);
+        let fan = super::ellipse_fan(&center, &na::Vector2::new(4.0, 2.0), 16);
+        assert_eq!(fan.len(), 16);
+        for (k, [c, p, q]) in fan.iter().enumerate() {
+            // Consecutive triangles share a point of the rim
+            assert_eq!(*c, center);
+            assert_eq!(*q, fan[(k + 1) % fan.len()][1]);
+            let e = ((p.x - 10.0) / 4.0).powi(2) + ((p.y - 10.0) / 2.0).powi(2);
+            assert!((e - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn scenes_are_checked_and_fixed() {
+        let p = |x, y| na::Point2::new(x, y);
+        let mut ct = ColoredTriangles {
+            colors: vec![graphics::WHITE, graphics::BLACK],
+            triangles: vec![
+                vec![
+                    // Fine
+                    p(0.0, 0.0),
+                    p(0.5, 0.0),
+                    p(0.0, 0.5),
+                    // Counterclockwise, with a vertex next to the first triangle
+                    p(0.50001, 0.0),
+                    p(0.0, 0.5),
+                    p(0.5, 0.5),
+                ],
+                vec![
+                    // Out of bounds, and a point repeated
+                    p(0.5, 0.5),
+                    p(1.015, 0.5),
+                    p(0.5, 0.5),
+                    // Flat
+                    p(0.0, 0.0),
+                    p(0.5, 0.5),
+                    p(1.0, 1.0),
+                ],
+            ],
+        };
+        let bounds = graphics::Rect::new(0.0, 0.0, 1.0, 1.0);
+        let before = ct.clone();
+        let report = ct.check(1e-3, &bounds);
+        assert_eq!(ct, before);
+        assert_eq!(report.welded, vec![(p(0.50001, 0.0), p(0.5, 0.0))]);
+        assert_eq!(report.degenerate, vec![(1, 0), (1, 1)]);
+        assert_eq!(report.out_of_bounds, vec![p(1.015, 0.5)]);
+        assert_eq!(report.reversed, vec![(0, 1)]);
+        assert!(!report.is_empty());
+
+        assert_eq!(ct.fix(1e-3, &bounds, true), report);
+        assert_eq!(
+            ct.triangles,
+            vec![
+                vec![
+                    p(0.0, 0.0),
+                    p(0.5, 0.0),
+                    p(0.0, 0.5),
+                    p(0.5, 0.0),
+                    p(0.5, 0.5),
+                    p(0.0, 0.5),
+                ],
+                vec![],
+            ]
+        );
+        assert!(ct.check(1e-3, &bounds).is_empty());
+        // Clamping moves vertices to the border
+        let mut ct = ColoredTriangles {
+            colors: vec![graphics::WHITE],
+            triangles: vec![vec![p(-0.006, 0.0), p(0.5, 0.0), p(0.0, 1.5)]],
+        };
+        assert_eq!(ct.fix(1e-3, &bounds, true).out_of_bounds.len(), 2);
+        assert_eq!(ct.triangles[0], vec![p(0.0, 0.0), p(0.5, 0.0), p(0.0, 1.0)]);
+    }
+
+    #[test]
+    fn outlines_of_triangles() {
+        let p = |x, y| na::Point2::new(x, y);
+        // Two triangles sharing an edge, and one with no area
+        let tris = [
+            p(0.0, 0.0),
+            p(1.0, 0.0),
+            p(0.0, 1.0),
+            p(1.0, 0.0),
+            p(1.0, 1.0),
+            p(0.0, 1.0),
+            p(5.0, 5.0),
+            p(5.0, 5.0),
+            p(6.0, 5.0),
+        ];
+        let edges = super::triangle_edges(&tris);
+        // Each triangle is closed, the shared edge is there once, and no edge
+        // links a triangle to the next
+        assert_eq!(edges.len(), 5 + 1);
+        assert!(edges.contains(&[p(0.0, 1.0), p(0.0, 0.0)]));
+        assert!(edges.contains(&[p(1.0, 0.0), p(0.0, 1.0)]));
+        assert!(!edges.contains(&[p(0.0, 1.0), p(1.0, 0.0)]));
+        assert!(!edges
+            .iter()
+            .any(|e| e.contains(&p(0.0, 1.0)) && e.contains(&p(5.0, 5.0))));
+
+        let open = super::polyline_edges(&tris[..3], false);
+        assert_eq!(
+            open,
+            vec![[p(0.0, 0.0), p(1.0, 0.0)], [p(1.0, 0.0), p(0.0, 1.0)]]
+        );
+        assert_eq!(super::polyline_edges(&tris[..3], true).len(), 3);
+        assert_eq!(super::polyline_edges(&tris[..2], true).len(), 1);
+    }
+
+    #[test]
+    fn strokes_cover_segments() {
+        let (a, b) = (na::Point2::new(1.0, 1.0), na::Point2::new(5.0, 1.0));
+        let tris = super::stroke(&[[a, b], [b, b]], 2.0);
+        // The empty segment is skipped, the other is a 6x2 rectangle
+        assert_eq!(tris.len(), 6);
+        let area: f32 = tris
+            .chunks(3)
+            .map(|t| super::cross(&t[0], &t[1], &t[2]).abs() * 0.5)
+            .sum();
+        assert!((area - 12.0).abs() < 1e-5);
+        assert!(tris
+            .iter()
+            .all(|p| (0.0..=6.0).contains(&p.x) && (0.0..=2.0).contains(&p.y)));
+
+        let squares = super::markers(&[a, b, a], 2.0);
+        assert_eq!(squares.len(), 12);
+        assert!(squares
+            .iter()
+            .all(|p| na::distance(p, &a) < 1.5 || na::distance(p, &b) < 1.5));
+    }
+
+    #[test]
+    fn indexed_triangles_hits_in_paint_order() {
+        let ct: ColoredTriangles =
+            "255 0,0 4,0 0,4 0,0 3,0 0,3\n65280 0,0 2,0 0,2\n16711680 9,9 9,8 8,9\n"
+                .parse()
+                .unwrap();
+        let it = IndexedTriangles::from(&ct);
+        let p = na::Point2::new(1.0, 1.0);
+        // Later colors are drawn on top, then later triangles of the same color
+        assert_eq!(it.colliding(&p), Some((1, 0)));
+        assert_eq!(it.hits(&p), vec![(1, 0), (0, 1), (0, 0)]);
+        assert_eq!(it.hits(&na::Point2::new(2.5, 1.0)), vec![(0, 0)]);
+        assert!(it.hits(&na::Point2::new(5.0, 5.0)).is_empty());
+    }
+
+    #[test]
+    fn indexed_triangles_queries_match_scan() {
+        use rand::prelude::*;
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut it = IndexedTriangles::from(&ColoredTriangles {
+            colors: vec![graphics::BLACK, graphics::WHITE],
+            triangles: vec![vec![], vec![]],
+        });
+        let pt = |rng: &mut StdRng| {
+            na::Point2::new(rng.gen_range(0.0, 500.0), rng.gen_range(0.0, 500.0))
+        };
+        for _ in 0..300 {
+            let p = pt(&mut rng);
+            let tri = [
+                p,
+                p + na::Vector2::new(rng.gen_range(-40.0, 40.0), rng.gen_range(-40.0, 40.0)),
+                p + na::Vector2::new(rng.gen_range(-40.0, 40.0), rng.gen_range(-40.0, 40.0)),
+            ];
+            it.add_triangle(rng.gen_range(0, 2), &tri);
+        }
+        // Edit the triangles in every way that updates the index
+        it.remove_triangles(&[(0, 3), (1, 10), (0, 50)]);
+        it.recolor(&[(0, 7), (1, 2)], 1);
+        it.detach(&[(1, 5)]);
+        let vs = it.vertices_of(&[(0, 1), (1, 4)]);
+        it.translate_vertices(&vs, &na::Vector2::new(30.0, -20.0));
+        it.move_vertices(&[(0, na::Point2::new(250.0, 250.0))]);
+        it.weld(1, 2);
+
+        for _ in 0..200 {
+            let p = pt(&mut rng);
+            let mut scan = it
+                .vertices
+                .iter()
+                .map(|v| na::distance_squared(v, &p))
+                .collect::<Vec<_>>();
+            scan.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let nearest = it.nearest(3, &p).iter().map(|n| n.1).collect::<Vec<_>>();
+            assert_eq!(nearest, scan[..3].to_vec());
+
+            // Scanning in paint order, the last hit is the one on top
+            let mut hits = (0..it.triangles.len())
+                .flat_map(|c| (0..it.triangles[c].len()).map(move |i| (c, i)))
+                .filter(|&(c, i)| {
+                    let [p1, p2, p3] = it.triangle(c, i);
+                    super::triangle_contains(&p1, &p2, &p3, &p)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(it.colliding(&p), hits.last().cloned());
+            hits.reverse();
+            assert_eq!(it.hits(&p), hits);
+        }
+    }
 }
@@ -0,0 +1,1003 @@
+//! Key bindings of Paint and Game, which can be changed with a TOML file like
+//!
+//! ```toml
+//! [paint]
+//! pan_left = ["H", "Left"]
+//! snap_grow = "*"
+//!
+//! [game]
+//! pause = "F10"
+//! ```
+//!
+//! Actions that are not in the file keep their default bindings.
+
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Names of keys, as written in the file; the first name of a key is used to show it
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("0", KeyCode::Key0),
+    ("1", KeyCode::Key1),
+    ("2", KeyCode::Key2),
+    ("3", KeyCode::Key3),
+    ("4", KeyCode::Key4),
+    ("5", KeyCode::Key5),
+    ("6", KeyCode::Key6),
+    ("7", KeyCode::Key7),
+    ("8", KeyCode::Key8),
+    ("9", KeyCode::Key9),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    ("Esc", KeyCode::Escape),
+    ("Escape", KeyCode::Escape),
+    ("Tab", KeyCode::Tab),
+    ("Enter", KeyCode::Return),
+    ("Return", KeyCode::Return),
+    ("Backspace", KeyCode::Back),
+    ("Back", KeyCode::Back),
+    ("Space", KeyCode::Space),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Equals", KeyCode::Equals),
+    ("Subtract", KeyCode::Subtract),
+    ("Minus", KeyCode::Minus),
+    ("Comma", KeyCode::Comma),
+    ("Period", KeyCode::Period),
+    ("LBracket", KeyCode::LBracket),
+    ("RBracket", KeyCode::RBracket),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Apostrophe", KeyCode::Apostrophe),
+    ("Grave", KeyCode::Grave),
+    ("Slash", KeyCode::Slash),
+    ("Backslash", KeyCode::Backslash),
+];
+
+/// Symbols and the keys typing them on US keyboards, with Shift or not
+const US_SYMBOLS: &[(char, KeyCode, bool)] = &[
+    ('-', KeyCode::Minus, false),
+    ('=', KeyCode::Equals, false),
+    ('[', KeyCode::LBracket, false),
+    (']', KeyCode::RBracket, false),
+    ('\\', KeyCode::Backslash, false),
+    (';', KeyCode::Semicolon, false),
+    ('\'', KeyCode::Apostrophe, false),
+    (',', KeyCode::Comma, false),
+    ('.', KeyCode::Period, false),
+    ('/', KeyCode::Slash, false),
+    ('`', KeyCode::Grave, false),
+    ('!', KeyCode::Key1, true),
+    ('@', KeyCode::Key2, true),
+    ('#', KeyCode::Key3, true),
+    ('$', KeyCode::Key4, true),
+    ('%', KeyCode::Key5, true),
+    ('^', KeyCode::Key6, true),
+    ('&', KeyCode::Key7, true),
+    ('*', KeyCode::Key8, true),
+    ('(', KeyCode::Key9, true),
+    (')', KeyCode::Key0, true),
+    ('_', KeyCode::Minus, true),
+    ('+', KeyCode::Equals, true),
+    ('{', KeyCode::LBracket, true),
+    ('}', KeyCode::RBracket, true),
+    ('|', KeyCode::Backslash, true),
+    (':', KeyCode::Semicolon, true),
+    ('"', KeyCode::Apostrophe, true),
+    ('<', KeyCode::Comma, true),
+    ('>', KeyCode::Period, true),
+    ('?', KeyCode::Slash, true),
+    ('~', KeyCode::Grave, true),
+];
+
+/// Key typing ch on US keyboards, if it's a symbol
+fn us_key(ch: char) -> Option<Binding> {
+    US_SYMBOLS
+        .iter()
+        .find(|&&(c, _, _)| c == ch)
+        .map(|&(_, code, shift)| Binding::Key {
+            code,
+            ctrl: false,
+            shift,
+            alt: false,
+        })
+}
+
+/// A key, with the modifiers that must be held while pressing it, or a typed character
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key {
+        code: KeyCode,
+        ctrl: bool,
+        shift: bool,
+        alt: bool,
+    },
+    /// Matched on the text typed, whatever keys the layout needs for it
+    Char(char),
+}
+
+impl Binding {
+    /// True if pressing code with mods triggers this binding
+    fn matches(&self, code: KeyCode, mods: KeyMods) -> bool {
+        match *self {
+            Binding::Key {
+                code: c,
+                ctrl,
+                shift,
+                alt,
+            } => {
+                c == code
+                    && ctrl == mods.contains(KeyMods::CTRL)
+                    && shift == mods.contains(KeyMods::SHIFT)
+                    && alt == mods.contains(KeyMods::ALT)
+            }
+            Binding::Char(_) => false,
+        }
+    }
+}
+
+/// Parses bindings like "N", "Ctrl+Shift+S" or "PageUp", ignoring case. A single
+/// character that is not a letter or digit, like "?", is a typed character
+impl FromStr for Binding {
+    type Err = KeymapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        if let (Some(ch), None) = (chars.next(), chars.next()) {
+            if !ch.is_ascii_alphanumeric() && !ch.is_whitespace() {
+                return Ok(Binding::Char(ch));
+            }
+        }
+        let (mut ctrl, mut shift, mut alt) = (false, false, false);
+        let mut name = s.trim();
+        while let Some(i) = name.find('+').filter(|&i| i > 0) {
+            match name[..i].trim().to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => ctrl = true,
+                "shift" => shift = true,
+                "alt" => alt = true,
+                _ => return Err(KeymapError::UnknownKey(s.to_owned())),
+            }
+            name = name[i + 1..].trim();
+        }
+        KEY_NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, code)| Binding::Key {
+                code,
+                ctrl,
+                shift,
+                alt,
+            })
+            .ok_or_else(|| KeymapError::UnknownKey(s.to_owned()))
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Binding::Key {
+                code,
+                ctrl,
+                shift,
+                alt,
+            } => {
+                if ctrl {
+                    write!(f, "Ctrl+")?;
+                }
+                if shift {
+                    write!(f, "Shift+")?;
+                }
+                if alt {
+                    write!(f, "Alt+")?;
+                }
+                match KEY_NAMES.iter().find(|&&(_, c)| c == code) {
+                    Some((name, _)) => write!(f, "{}", name),
+                    None => write!(f, "{:?}", code),
+                }
+            }
+            Binding::Char(ch) => write!(f, "{}", ch),
+        }
+    }
+}
+
+/// Errors found reading key bindings
+#[derive(Debug)]
+pub enum KeymapError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    UnknownSection(String),
+    /// Section and name of the action
+    UnknownAction(String, String),
+    /// Action whose value is not a binding or a list of them
+    NotBindings(String),
+    UnknownKey(String),
+    /// Action bound to a character where only keys can be used
+    CharNotAllowed(String, char),
+    /// A binding used by two actions
+    Conflict(Binding, String, String),
+    /// A character typed with a key that is bound too, as both actions would
+    /// run: the character, the key (on US keyboards) and the two actions
+    CharConflict(char, Binding, String, String),
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeymapError::Io(e) => write!(f, "{}", e),
+            KeymapError::Toml(e) => write!(f, "{}", e),
+            KeymapError::UnknownSection(s) => write!(f, "unknown section [{}]", s),
+            KeymapError::UnknownAction(s, a) => write!(f, "unknown action {} in [{}]", a, s),
+            KeymapError::NotBindings(a) => {
+                write!(f, "{} must be a key or a list of keys", a)
+            }
+            KeymapError::UnknownKey(k) => write!(f, "unknown key {:?}", k),
+            KeymapError::CharNotAllowed(a, ch) => {
+                write!(
+                    f,
+                    "{} can't be bound to {:?}, which is typed in game",
+                    a, ch
+                )
+            }
+            KeymapError::Conflict(b, a1, a2) => {
+                write!(f, "{} is bound to both {} and {}", b, a1, a2)
+            }
+            KeymapError::CharConflict(ch, b, a1, a2) => {
+                write!(
+                    f,
+                    "{} is typed with {}, bound to both {} and {}",
+                    ch, b, a1, a2
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+/// An action, with its name in the file, a description and its default bindings
+pub struct ActionInfo<A: 'static> {
+    pub action: A,
+    pub name: &'static str,
+    pub help: &'static str,
+    pub keys: &'static [&'static str],
+}
+
+/// Actions that can be bound to keys, all in one section of the file
+pub trait Action: Copy + PartialEq + 'static {
+    /// Name of the section in the file
+    const SECTION: &'static str;
+    /// Whether typed characters can be bound, or they are kept for typing
+    const CHARS: bool;
+    /// Every action, in the order they are listed in help
+    fn table() -> &'static [ActionInfo<Self>];
+}
+
+/// Bindings from keys to actions of one section
+#[derive(Clone, Debug)]
+pub struct Keymap<A> {
+    bindings: Vec<(Binding, A)>,
+}
+
+impl<A: Action> Default for Keymap<A> {
+    fn default() -> Self {
+        Keymap::from_table(None).expect("default bindings are valid")
+    }
+}
+
+impl<A: Action> Keymap<A> {
+    /// Builds bindings from a section of the file, with defaults for missing actions
+    fn from_table(table: Option<&toml::value::Table>) -> Result<Self, KeymapError> {
+        if let Some(table) = table {
+            if let Some(name) = table
+                .keys()
+                .find(|&n| !A::table().iter().any(|info| info.name == n))
+            {
+                return Err(KeymapError::UnknownAction(
+                    A::SECTION.to_owned(),
+                    name.clone(),
+                ));
+            }
+        }
+        let name = |action: A| {
+            let info = A::table().iter().find(|i| i.action == action).unwrap();
+            format!("{}.{}", A::SECTION, info.name)
+        };
+        let mut bindings: Vec<(Binding, A)> = vec![];
+        for info in A::table() {
+            let full_name = format!("{}.{}", A::SECTION, info.name);
+            let keys: Vec<String> = match table.and_then(|t| t.get(info.name)) {
+                None => info.keys.iter().map(|&k| k.to_owned()).collect(),
+                Some(toml::Value::String(k)) => vec![k.clone()],
+                Some(toml::Value::Array(ks)) => ks
+                    .iter()
+                    .map(|k| k.as_str().map(str::to_owned))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| KeymapError::NotBindings(full_name.clone()))?,
+                Some(_) => return Err(KeymapError::NotBindings(full_name)),
+            };
+            for key in keys {
+                let binding: Binding = key.parse()?;
+                match binding {
+                    Binding::Char(ch) if !A::CHARS => {
+                        return Err(KeymapError::CharNotAllowed(full_name, ch));
+                    }
+                    _ => {}
+                }
+                if let Some(&(_, other)) = bindings.iter().find(|(b, _)| *b == binding) {
+                    return Err(KeymapError::Conflict(binding, name(other), full_name));
+                }
+                bindings.push((binding, info.action));
+            }
+        }
+        // A key typing a bound character would run both actions, at least on
+        // US keyboards (other layouts can't be known in advance)
+        for &(binding, action) in &bindings {
+            if let Binding::Char(ch) = binding {
+                let key = match us_key(ch) {
+                    Some(key) => key,
+                    None => continue,
+                };
+                if let Some(&(_, other)) = bindings.iter().find(|(b, _)| *b == key) {
+                    return Err(KeymapError::CharConflict(
+                        ch,
+                        key,
+                        name(other),
+                        name(action),
+                    ));
+                }
+            }
+        }
+        Ok(Keymap { bindings })
+    }
+
+    /// Action bound to pressing code with mods, if any
+    pub fn action(&self, code: KeyCode, mods: KeyMods) -> Option<A> {
+        self.bindings
+            .iter()
+            .find(|(b, _)| b.matches(code, mods))
+            .map(|&(_, a)| a)
+    }
+
+    /// Action bound to typing ch, if any
+    pub fn char_action(&self, ch: char) -> Option<A> {
+        self.bindings
+            .iter()
+            .find(|&&(b, _)| b == Binding::Char(ch))
+            .map(|&(_, a)| a)
+    }
+
+    /// Bindings of action, in the order they were written
+    pub fn bindings(&self, action: A) -> Vec<Binding> {
+        self.bindings
+            .iter()
+            .filter(|&&(_, a)| a == action)
+            .map(|&(b, _)| b)
+            .collect()
+    }
+
+    /// First key bound to action without modifiers, if any
+    pub fn key(&self, action: A) -> Option<KeyCode> {
+        self.bindings(action).into_iter().find_map(|b| match b {
+            Binding::Key {
+                code,
+                ctrl: false,
+                shift: false,
+                alt: false,
+            } => Some(code),
+            _ => None,
+        })
+    }
+
+    /// One line for each action, with its bindings and description
+    pub fn help(&self) -> Vec<String> {
+        A::table()
+            .iter()
+            .map(|info| {
+                let keys = self.bindings(info.action);
+                let keys = if keys.is_empty() {
+                    "(none)".to_owned()
+                } else {
+                    let keys: Vec<String> = keys.iter().map(|b| b.to_string()).collect();
+                    keys.join(", ")
+                };
+                format!("  {:<18}{}", keys, info.help)
+            })
+            .collect()
+    }
+}
+
+/// Actions of Paint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaintAction {
+    Help,
+    Cancel,
+    Save,
+    NextTool,
+    ClosePolygon,
+    RemovePoint,
+    Recolor,
+    DeleteSelection,
+    Copy,
+    Paste,
+    DeleteUnderCursor,
+    NextColor,
+    PreviousColor,
+    AddColor,
+    MoveColorUp,
+    MoveColorDown,
+    HideColor,
+    LockColor,
+    LessAlpha,
+    MoreAlpha,
+    RemoveColor,
+    TogglePalette,
+    ToggleSnap,
+    ToggleEdgeSnap,
+    ToggleGrid,
+    SnapGrow,
+    SnapShrink,
+    GridGrow,
+    GridShrink,
+    PanLeft,
+    PanDown,
+    PanUp,
+    PanRight,
+    ZoomIn,
+    ZoomOut,
+    ResetView,
+    MoveLeft,
+    MoveDown,
+    MoveUp,
+    MoveRight,
+    ScaleUp,
+    ScaleDown,
+    ToggleWireframe,
+    ToggleReference,
+    ReferenceFainter,
+    ReferenceStronger,
+}
+
+const PAINT_ACTIONS: &[ActionInfo<PaintAction>] = &[
+    ActionInfo {
+        action: PaintAction::Help,
+        name: "help",
+        help: "show or hide this help",
+        keys: &["F1", "?"],
+    },
+    ActionInfo {
+        action: PaintAction::Cancel,
+        name: "cancel",
+        help: "cancel drawing, clear selection, or save and quit",
+        keys: &["Escape"],
+    },
+    ActionInfo {
+        action: PaintAction::Save,
+        name: "save",
+        help: "save",
+        keys: &["Ctrl+S"],
+    },
+    ActionInfo {
+        action: PaintAction::NextTool,
+        name: "next_tool",
+        help: "triangle, polygon, ellipse and select tool",
+        keys: &["Tab"],
+    },
+    ActionInfo {
+        action: PaintAction::ClosePolygon,
+        name: "close_polygon",
+        help: "close the polygon being drawn",
+        keys: &["Return"],
+    },
+    ActionInfo {
+        action: PaintAction::RemovePoint,
+        name: "remove_point",
+        help: "remove the last point clicked",
+        keys: &["Back"],
+    },
+    ActionInfo {
+        action: PaintAction::Recolor,
+        name: "recolor",
+        help: "move selection on top of current color",
+        keys: &["R"],
+    },
+    ActionInfo {
+        action: PaintAction::DeleteSelection,
+        name: "delete_selection",
+        help: "remove selection",
+        keys: &["Delete"],
+    },
+    ActionInfo {
+        action: PaintAction::Copy,
+        name: "copy",
+        help: "copy selection",
+        keys: &["Ctrl+C"],
+    },
+    ActionInfo {
+        action: PaintAction::Paste,
+        name: "paste",
+        help: "paste at the cursor",
+        keys: &["Ctrl+V"],
+    },
+    ActionInfo {
+        action: PaintAction::DeleteUnderCursor,
+        name: "delete_under_cursor",
+        help: "remove the topmost triangle under cursor",
+        keys: &["X"],
+    },
+    ActionInfo {
+        action: PaintAction::NextColor,
+        name: "next_color",
+        help: "next color",
+        keys: &["N"],
+    },
+    ActionInfo {
+        action: PaintAction::PreviousColor,
+        name: "previous_color",
+        help: "previous color",
+        keys: &["Shift+N"],
+    },
+    ActionInfo {
+        action: PaintAction::AddColor,
+        name: "add_color",
+        help: "add a color (type it, then Enter)",
+        keys: &["#"],
+    },
+    ActionInfo {
+        action: PaintAction::MoveColorUp,
+        name: "move_color_up",
+        help: "move current color up",
+        keys: &["PageUp"],
+    },
+    ActionInfo {
+        action: PaintAction::MoveColorDown,
+        name: "move_color_down",
+        help: "move current color down",
+        keys: &["PageDown"],
+    },
+    ActionInfo {
+        action: PaintAction::HideColor,
+        name: "hide_color",
+        help: "hide or show current color",
+        keys: &["Ctrl+H"],
+    },
+    ActionInfo {
+        action: PaintAction::LockColor,
+        name: "lock_color",
+        help: "lock or unlock current color",
+        keys: &["Ctrl+L"],
+    },
+    ActionInfo {
+        action: PaintAction::LessAlpha,
+        name: "less_alpha",
+        help: "make current color more transparent",
+        keys: &["Comma"],
+    },
+    ActionInfo {
+        action: PaintAction::MoreAlpha,
+        name: "more_alpha",
+        help: "make current color more opaque",
+        keys: &["Period"],
+    },
+    ActionInfo {
+        action: PaintAction::RemoveColor,
+        name: "remove_color",
+        help: "remove current color and its triangles",
+        keys: &["Ctrl+Delete"],
+    },
+    ActionInfo {
+        action: PaintAction::TogglePalette,
+        name: "toggle_palette",
+        help: "show or hide palette",
+        keys: &["P"],
+    },
+    ActionInfo {
+        action: PaintAction::ToggleSnap,
+        name: "toggle_snap",
+        help: "snap to points",
+        keys: &["S"],
+    },
+    ActionInfo {
+        action: PaintAction::ToggleEdgeSnap,
+        name: "toggle_edge_snap",
+        help: "snap to edges",
+        keys: &["E"],
+    },
+    ActionInfo {
+        action: PaintAction::ToggleGrid,
+        name: "toggle_grid",
+        help: "snap to grid",
+        keys: &["G"],
+    },
+    ActionInfo {
+        action: PaintAction::SnapGrow,
+        name: "snap_grow",
+        help: "increase snap distance",
+        keys: &["Equals"],
+    },
+    ActionInfo {
+        action: PaintAction::SnapShrink,
+        name: "snap_shrink",
+        help: "decrease snap distance",
+        keys: &["Subtract"],
+    },
+    ActionInfo {
+        action: PaintAction::GridGrow,
+        name: "grid_grow",
+        help: "double grid spacing",
+        keys: &["Shift+Equals"],
+    },
+    ActionInfo {
+        action: PaintAction::GridShrink,
+        name: "grid_shrink",
+        help: "halve grid spacing",
+        keys: &["Shift+Subtract"],
+    },
+    ActionInfo {
+        action: PaintAction::PanLeft,
+        name: "pan_left",
+        help: "pan the view left",
+        keys: &["H"],
+    },
+    ActionInfo {
+        action: PaintAction::PanDown,
+        name: "pan_down",
+        help: "pan the view down",
+        keys: &["J"],
+    },
+    ActionInfo {
+        action: PaintAction::PanUp,
+        name: "pan_up",
+        help: "pan the view up",
+        keys: &["K"],
+    },
+    ActionInfo {
+        action: PaintAction::PanRight,
+        name: "pan_right",
+        help: "pan the view right",
+        keys: &["L"],
+    },
+    ActionInfo {
+        action: PaintAction::ZoomIn,
+        name: "zoom_in",
+        help: "zoom in",
+        keys: &["I"],
+    },
+    ActionInfo {
+        action: PaintAction::ZoomOut,
+        name: "zoom_out",
+        help: "zoom out",
+        keys: &["O"],
+    },
+    ActionInfo {
+        action: PaintAction::ResetView,
+        name: "reset_view",
+        help: "reset the view",
+        keys: &["Home"],
+    },
+    ActionInfo {
+        action: PaintAction::MoveLeft,
+        name: "move_left",
+        help: "move the scene left",
+        keys: &["Shift+H"],
+    },
+    ActionInfo {
+        action: PaintAction::MoveDown,
+        name: "move_down",
+        help: "move the scene down",
+        keys: &["Shift+J"],
+    },
+    ActionInfo {
+        action: PaintAction::MoveUp,
+        name: "move_up",
+        help: "move the scene up",
+        keys: &["Shift+K"],
+    },
+    ActionInfo {
+        action: PaintAction::MoveRight,
+        name: "move_right",
+        help: "move the scene right",
+        keys: &["Shift+L"],
+    },
+    ActionInfo {
+        action: PaintAction::ScaleUp,
+        name: "scale_up",
+        help: "scale the scene up",
+        keys: &["Shift+I"],
+    },
+    ActionInfo {
+        action: PaintAction::ScaleDown,
+        name: "scale_down",
+        help: "scale the scene down",
+        keys: &["Shift+O"],
+    },
+    ActionInfo {
+        action: PaintAction::ToggleWireframe,
+        name: "toggle_wireframe",
+        help: "wireframe",
+        keys: &["W"],
+    },
+    ActionInfo {
+        action: PaintAction::ToggleReference,
+        name: "toggle_reference",
+        help: "show or hide reference image",
+        keys: &["B"],
+    },
+    ActionInfo {
+        action: PaintAction::ReferenceFainter,
+        name: "reference_fainter",
+        help: "make reference image fainter",
+        keys: &["LBracket"],
+    },
+    ActionInfo {
+        action: PaintAction::ReferenceStronger,
+        name: "reference_stronger",
+        help: "make reference image stronger",
+        keys: &["RBracket"],
+    },
+];
+
+impl Action for PaintAction {
+    const SECTION: &'static str = "paint";
+    const CHARS: bool = true;
+
+    fn table() -> &'static [ActionInfo<Self>] {
+        PAINT_ACTIONS
+    }
+}
+
+/// Actions of Game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameAction {
+    Pause,
+}
+
+const GAME_ACTIONS: &[ActionInfo<GameAction>] = &[ActionInfo {
+    action: GameAction::Pause,
+    name: "pause",
+    // F10 pauses while Escape is being practised
+    help: "pause the game",
+    keys: &["Escape", "F10"],
+}];
+
+impl Action for GameAction {
+    const SECTION: &'static str = "game";
+    // Characters are typed to hit enemies
+    const CHARS: bool = false;
+
+    fn table() -> &'static [ActionInfo<Self>] {
+        GAME_ACTIONS
+    }
+}
+
+/// Bindings of every section
+#[derive(Clone, Debug, Default)]
+pub struct Keymaps {
+    pub paint: Keymap<PaintAction>,
+    pub game: Keymap<GameAction>,
+}
+
+impl FromStr for Keymaps {
+    type Err = KeymapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value: toml::Value = s.parse().map_err(KeymapError::Toml)?;
+        let table = value.as_table().expect("TOML documents are tables");
+        let section = |name: &str| match table.get(name) {
+            None => Ok(None),
+            Some(toml::Value::Table(t)) => Ok(Some(t)),
+            Some(_) => Err(KeymapError::UnknownSection(name.to_owned())),
+        };
+        if let Some(name) = table
+            .keys()
+            .find(|&n| n != PaintAction::SECTION && n != GameAction::SECTION)
+        {
+            return Err(KeymapError::UnknownSection(name.clone()));
+        }
+        Ok(Keymaps {
+            paint: Keymap::from_table(section(PaintAction::SECTION)?)?,
+            game: Keymap::from_table(section(GameAction::SECTION)?)?,
+        })
+    }
+}
+
+impl Keymaps {
+    /// Reads bindings from path or, if None, from keys.toml in the configuration
+    /// directory, using defaults when that file does not exist
+    pub fn load(path: Option<&Path>) -> Result<Self, KeymapError> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match crate::config_path("keys.toml") {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Keymaps::default()),
+            },
+        };
+        std::fs::read_to_string(path)
+            .map_err(KeymapError::Io)?
+            .parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Binding, GameAction, KeymapError, Keymaps, PaintAction};
+    use ggez::input::keyboard::{KeyCode, KeyMods};
+
+    #[test]
+    fn bindings_from_string() {
+        let key = |code, ctrl, shift, alt| Binding::Key {
+            code,
+            ctrl,
+            shift,
+            alt,
+        };
+        assert_eq!(
+            "n".parse::<Binding>().unwrap(),
+            key(KeyCode::N, false, false, false)
+        );
+        assert_eq!(
+            "Ctrl+Shift+s".parse::<Binding>().unwrap(),
+            key(KeyCode::S, true, true, false)
+        );
+        assert_eq!(
+            "alt + PageUp".parse::<Binding>().unwrap(),
+            key(KeyCode::PageUp, false, false, true)
+        );
+        assert_eq!(
+            "Esc".parse::<Binding>().unwrap(),
+            key(KeyCode::Escape, false, false, false)
+        );
+        assert_eq!("?".parse::<Binding>().unwrap(), Binding::Char('?'));
+        assert_eq!("+".parse::<Binding>().unwrap(), Binding::Char('+'));
+        assert!("Hyper+A".parse::<Binding>().is_err());
+        assert!("Ctrl+".parse::<Binding>().is_err());
+        assert!("Ctrl+?".parse::<Binding>().is_err());
+        // Bindings are shown as they can be written
+        for s in &["Ctrl+Shift+S", "Esc", "?", "F1", "Alt+Backspace"] {
+            assert_eq!(s.parse::<Binding>().unwrap().to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn default_keymaps() {
+        let keys = Keymaps::default();
+        let paint = |code, mods| keys.paint.action(code, mods);
+        assert_eq!(
+            paint(KeyCode::N, KeyMods::NONE),
+            Some(PaintAction::NextColor)
+        );
+        assert_eq!(
+            paint(KeyCode::N, KeyMods::SHIFT),
+            Some(PaintAction::PreviousColor)
+        );
+        assert_eq!(paint(KeyCode::S, KeyMods::CTRL), Some(PaintAction::Save));
+        assert_eq!(
+            paint(KeyCode::S, KeyMods::NONE),
+            Some(PaintAction::ToggleSnap)
+        );
+        assert_eq!(
+            paint(KeyCode::Equals, KeyMods::SHIFT),
+            Some(PaintAction::GridGrow)
+        );
+        assert_eq!(paint(KeyCode::N, KeyMods::CTRL), None);
+        assert_eq!(keys.paint.char_action('?'), Some(PaintAction::Help));
+        assert_eq!(keys.paint.char_action('#'), Some(PaintAction::AddColor));
+        assert_eq!(
+            keys.game.action(KeyCode::Escape, KeyMods::NONE),
+            Some(GameAction::Pause)
+        );
+        assert_eq!(keys.game.key(GameAction::Pause), Some(KeyCode::Escape));
+        // Every action is listed in help
+        assert!(keys.paint.help().iter().all(|l| !l.contains("(none)")));
+    }
+
+    #[test]
+    fn keymaps_from_toml() {
+        let keys: Keymaps = "[paint]\npan_left = [\"A\", \"Left\"]\nsnap_grow = \"*\"\n\
+                             [game]\npause = \"F10\""
+            .parse()
+            .unwrap();
+        assert_eq!(
+            keys.paint.action(KeyCode::A, KeyMods::NONE),
+            Some(PaintAction::PanLeft)
+        );
+        assert_eq!(
+            keys.paint.action(KeyCode::Left, KeyMods::NONE),
+            Some(PaintAction::PanLeft)
+        );
+        // Rebound actions lose their default keys, the others keep them
+        assert_eq!(keys.paint.action(KeyCode::H, KeyMods::NONE), None);
+        assert_eq!(keys.paint.action(KeyCode::Equals, KeyMods::NONE), None);
+        assert_eq!(keys.paint.char_action('*'), Some(PaintAction::SnapGrow));
+        assert_eq!(
+            keys.paint.action(KeyCode::J, KeyMods::NONE),
+            Some(PaintAction::PanDown)
+        );
+        assert_eq!(keys.game.action(KeyCode::Escape, KeyMods::NONE), None);
+        assert_eq!(
+            keys.game.action(KeyCode::F10, KeyMods::NONE),
+            Some(GameAction::Pause)
+        );
+
+        let err = |s: &str| s.parse::<Keymaps>().unwrap_err();
+        match err("[paint]\npan_left = \"J\"") {
+            KeymapError::Conflict(_, a, b) => assert_eq!(
+                (a.as_str(), b.as_str()),
+                ("paint.pan_left", "paint.pan_down")
+            ),
+            e => panic!("unexpected error {}", e),
+        }
+        // Shift+Equals types + and grows the grid
+        let e = err("[paint]\nsnap_grow = \"+\"");
+        assert_eq!(
+            e.to_string(),
+            "+ is typed with Shift+Equals, bound to both paint.grid_grow and paint.snap_grow"
+        );
+        assert!(
+            "[paint]\nsnap_grow = \"+\"\ngrid_grow = \"G\"\ntoggle_grid = \"F2\""
+                .parse::<Keymaps>()
+                .is_ok()
+        );
+        assert!(matches!(
+            err("[paint]\nfly = \"F\""),
+            KeymapError::UnknownAction(..)
+        ));
+        assert!(matches!(err("[menu]\n"), KeymapError::UnknownSection(..)));
+        assert!(matches!(
+            err("[paint]\nsave = 1"),
+            KeymapError::NotBindings(..)
+        ));
+        assert!(matches!(
+            err("[paint]\nsave = \"Ctrl+Nope\""),
+            KeymapError::UnknownKey(..)
+        ));
+        assert!(matches!(
+            err("[game]\npause = \"!\""),
+            KeymapError::CharNotAllowed(..)
+        ));
+        assert!(matches!(err("[paint"), KeymapError::Toml(..)));
+    }
+}
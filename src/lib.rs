@@ -1,13 +1,30 @@
 //! Cool stuff and states
 
-mod drawing;
+pub mod difficulty;
+pub mod drawing;
+pub mod keymap;
+pub mod menu;
 mod objects;
+pub mod palette;
+pub mod settings;
+mod spatial;
+pub mod stats;
 pub mod wording;
 
 use objects::{Enemy, Player};
-use wording::{Enemies, WordProducer};
+use wording::{Course, Enemies, WordProducer};
 
-use drawing::ColoredTriangles;
+use difficulty::{Adaptive, Difficulty, Preset};
+use drawing::{
+    col2str, ellipse_fan, markers, polyline_edges, str2col, stroke, triangle_edges, triangulate,
+    ColoredTriangles, IndexedTriangles, SceneError,
+};
+use keymap::{GameAction, Keymap, PaintAction};
+pub use menu::MenuEntry;
+use menu::{Menu, Shared};
+use palette::Palette;
+use settings::Settings;
+use stats::{LevelReport, Stats};
 
 use std::io::Write;
 
@@ -23,24 +40,131 @@ use ggez::{
 use rand::prelude::*;
 use rand_distr::{Bernoulli, Distribution, Exp};
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum States {
     StartScreen,
     Game,
     Pause,
     NextLevel,
     Victory,
+    /// Choice of the course to play
+    Courses,
+    Settings,
     Quit,
     Paint,
 }
 
+/// Data carried by a change of state to the state entered
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    None,
+    /// Level (lesson) to start playing from
+    Level(usize),
+    /// Play again the last level played
+    Retry,
+    /// Play the level after the last one played
+    Next,
+    /// Course to play, from the given level
+    Course(Course, usize),
+    /// How the level just played went, shown between levels
+    Report(Box<LevelReport>),
+    /// Why the state left couldn't go on, shown as a report
+    Error(String),
+}
+
+/// A change of state, requested by the running state
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateChange {
+    /// Leave every running state and enter another one
+    Switch(States, Payload),
+    /// Enter a state over the running one, which is kept to get back to it
+    Push(States, Payload),
+    /// Leave the running state, getting back to the one below it; the bottom
+    /// state can't be left this way, and keeps running
+    Pop(Payload),
+}
+
+/// Switching to a state, with no payload
+impl From<States> for StateChange {
+    fn from(state: States) -> Self {
+        StateChange::Switch(state, Payload::None)
+    }
+}
+
 pub trait State: EventHandler {
-    fn next_state(&mut self) -> Option<States>;
+    fn next_state(&mut self) -> Option<StateChange>;
+
+    /// Called when the state is switched or pushed to, with the payload of the change
+    fn on_enter(&mut self, _ctx: &mut Context, _payload: Payload) {}
+
+    /// Called when the state is left, switching to another or popping it
+    fn on_exit(&mut self, _ctx: &mut Context) {}
+
+    /// Called when another state is pushed over this one
+    fn on_cover(&mut self, _ctx: &mut Context) {}
+
+    /// Called when the state above this one is popped, with the payload of the change
+    fn on_resume(&mut self, _ctx: &mut Context, _payload: Payload) {}
+
+    /// True if the states below this one are drawn too, under it
+    fn is_overlay(&self) -> bool {
+        false
+    }
+}
+
+/// A hook of a state, called when the stack of states changes
+#[derive(Clone, Debug, PartialEq)]
+enum Hook {
+    Enter(Payload),
+    Exit,
+    Cover,
+    Resume(Payload),
+}
+
+/// Applies a change to a stack of states, returning the hooks to call in order
+/// and the states to call them on
+fn change_stack(stack: &mut Vec<States>, change: StateChange) -> Vec<(States, Hook)> {
+    let mut hooks = vec![];
+    match change {
+        StateChange::Switch(next, payload) => {
+            while let Some(s) = stack.pop() {
+                hooks.push((s, Hook::Exit));
+            }
+            hooks.push((next.clone(), Hook::Enter(payload)));
+            stack.push(next);
+        }
+        StateChange::Push(next, payload) => {
+            if let Some(s) = stack.last().cloned() {
+                hooks.push((s, Hook::Cover));
+            }
+            hooks.push((next.clone(), Hook::Enter(payload)));
+            stack.push(next);
+        }
+        StateChange::Pop(payload) if stack.len() > 1 => {
+            let s = stack.pop().unwrap();
+            hooks.push((s, Hook::Exit));
+            let s = stack.last().cloned().unwrap();
+            hooks.push((s, Hook::Resume(payload)));
+        }
+        // Nothing to get back to, the bottom state keeps running
+        StateChange::Pop(_) => {}
+    }
+    hooks
 }
 
 // A map from enum to EventHandler
 pub type StateMap = std::collections::HashMap<States, Box<dyn State>>;
 
+/// Path of a configuration file of the game, under $XDG_CONFIG_HOME/rs-type or
+/// ~/.config/rs-type, None if neither variable is set
+pub fn config_path(name: &str) -> Option<std::path::PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| std::path::Path::new(&h).join(".config")))?;
+    Some(dir.join("rs-type").join(name))
+}
+
 /// Manages the state of the game, also rendering background
 pub struct Manager {
     stars: Vec<(f32, f32)>,
@@ -48,7 +172,8 @@ pub struct Manager {
     space: graphics::Color,
 
     states: StateMap,
-    current_state: States,
+    // Running states, the last one on top gets events
+    stack: Vec<States>,
 }
 
 impl Manager {
@@ -64,7 +189,9 @@ impl Manager {
                 )
             })
             .collect();
-        let mut planets: ColoredTriangles = include_str!("planets.txt").into();
+        let mut planets: ColoredTriangles = include_str!("planets.txt")
+            .parse()
+            .expect("Unable to parse planets");
         // Assume it's normalized
         planets.scale(width, height);
         // println!("Planets {:?}", planets.triangles[0]);
@@ -79,27 +206,62 @@ impl Manager {
         let planets = builder.build(ctx).expect("Unable to build planets mesh");
 
         // TODO prepare here option texts
-        Manager {
+        let mut manager = Manager {
             stars,
             planets,
-            current_state: initial_state,
+            stack: vec![],
             states,
             space: (22, 2, 33).into(),
+        };
+        manager.change(ctx, initial_state.into());
+        manager
+    }
+
+    /// A registered state
+    fn state(&mut self, state: &States) -> &mut dyn State {
+        self.states
+            .get_mut(state)
+            .expect("Cannot get state")
+            .as_mut()
+    }
+
+    /// Applies a change of state, calling the hooks of the states involved
+    fn change(&mut self, ctx: &mut Context, change: StateChange) {
+        for (s, hook) in change_stack(&mut self.stack, change) {
+            let state = self.state(&s);
+            match hook {
+                Hook::Enter(payload) => state.on_enter(ctx, payload),
+                Hook::Exit => state.on_exit(ctx),
+                Hook::Cover => state.on_cover(ctx),
+                Hook::Resume(payload) => state.on_resume(ctx, payload),
+            }
         }
+        // The state on top might ask to be left right away, e.g. if it can't start
+        let top = self.stack.last().cloned();
+        if let Some(change) = top.and_then(|s| self.state(&s).next_state()) {
+            self.change(ctx, change);
+        }
+    }
+
+    /// Calls f on the state on top, then applies the change of state it asks for
+    fn on_top<R, F: FnOnce(&mut dyn State, &mut Context) -> R>(
+        &mut self,
+        ctx: &mut Context,
+        f: F,
+    ) -> R {
+        let top = self.stack.last().cloned().expect("No running state");
+        let st = self.state(&top);
+        let res = f(st, ctx);
+        if let Some(change) = st.next_state() {
+            self.change(ctx, change);
+        }
+        res
     }
 }
 
 impl EventHandler for Manager {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        let res = st.update(ctx);
-        if let Some(ns) = st.next_state() {
-            self.current_state = ns
-        }
-        res
+        self.on_top(ctx, |st, ctx| st.update(ctx))
     }
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         // Draw background
@@ -132,22 +294,21 @@ impl EventHandler for Manager {
         graphics::draw(ctx, &stars, graphics::DrawParam::default())?;
         graphics::draw(ctx, &self.planets, graphics::DrawParam::default())?;
 
-        // Let state to draw the rest
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        st.draw(ctx)
+        // Let states draw the rest, starting from the topmost one that is not an
+        // overlay, so states below overlays are still visible
+        let first = self
+            .stack
+            .iter()
+            .rposition(|s| !self.states[s].is_overlay())
+            .unwrap_or(0);
+        for s in &self.stack[first..] {
+            let st = self.states.get_mut(s).expect("Cannot get state");
+            st.draw(ctx)?;
+        }
+        graphics::present(ctx)
     }
     fn text_input_event(&mut self, ctx: &mut Context, ch: char) {
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        st.text_input_event(ctx, ch);
-        if let Some(ns) = st.next_state() {
-            self.current_state = ns
-        }
+        self.on_top(ctx, |st, ctx| st.text_input_event(ctx, ch))
     }
     /// Called when Esc is pressed
     fn key_down_event(
@@ -157,48 +318,29 @@ impl EventHandler for Manager {
         keymods: KeyMods,
         repeat: bool,
     ) {
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        st.key_down_event(ctx, keycode, keymods, repeat);
-        if let Some(ns) = st.next_state() {
-            self.current_state = ns
-        }
+        self.on_top(ctx, |st, ctx| {
+            st.key_down_event(ctx, keycode, keymods, repeat)
+        })
     }
     /// Called when mouse button is pressed
     fn mouse_motion_event(&mut self, ctx: &mut Context, x: f32, y: f32, dx: f32, dy: f32) {
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        st.mouse_motion_event(ctx, x, y, dx, dy);
-        if let Some(ns) = st.next_state() {
-            self.current_state = ns
-        }
+        self.on_top(ctx, |st, ctx| st.mouse_motion_event(ctx, x, y, dx, dy))
     }
     /// Called when mouse button is pressed
     fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        st.mouse_button_down_event(ctx, button, x, y);
-        if let Some(ns) = st.next_state() {
-            self.current_state = ns
-        }
+        self.on_top(ctx, |st, ctx| st.mouse_button_down_event(ctx, button, x, y))
+    }
+    /// Called when mouse button is released
+    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        self.on_top(ctx, |st, ctx| st.mouse_button_up_event(ctx, button, x, y))
+    }
+    /// Called when mouse wheel is scrolled
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, x: f32, y: f32) {
+        self.on_top(ctx, |st, ctx| st.mouse_wheel_event(ctx, x, y))
     }
     /// Called when window is closed
     fn quit_event(&mut self, ctx: &mut Context) -> bool {
-        let st = self
-            .states
-            .get_mut(&self.current_state)
-            .expect("Cannot get state");
-        let qe = st.quit_event(ctx);
-        if let Some(ns) = st.next_state() {
-            self.current_state = ns
-        }
-        qe
+        self.on_top(ctx, |st, ctx| st.quit_event(ctx))
     }
 }
 
@@ -214,25 +356,18 @@ impl EventHandler for Quit {
 }
 
 impl State for Quit {
-    fn next_state(&mut self) -> Option<States> {
+    fn next_state(&mut self) -> Option<StateChange> {
         None
     }
 }
 
-pub enum MenuEntry {
-    /// A message that displays a message and transitions to another state when key is pressed
-    Transition(f32, String, KeyCode, States),
-    /// Just a message text, no transition
-    Message(f32, String),
-    /// Some vertical space between entries
-    VSpace(f32),
-}
-
 pub struct UI {
-    goto_state: Option<States>,
-    // This is a vector of things to show on the screen
-    options: Vec<MenuEntry>,
+    goto_state: Option<StateChange>,
+    // The things to show on the screen, and which one is selected
+    menu: Menu,
     font: graphics::Font,
+    // Drawn over the states below, dimming them
+    overlay: bool,
 }
 
 impl UI {
@@ -245,8 +380,18 @@ impl UI {
             .unwrap_or_default();
         UI {
             goto_state: None,
-            options,
+            menu: Menu::new(options),
             font,
+            overlay: false,
+        }
+    }
+
+    /// A UI drawn over a dimmed view of the state below it, which is meant to
+    /// be pushed
+    pub fn overlay(ctx: &mut Context, options: Vec<MenuEntry>) -> UI {
+        UI {
+            overlay: true,
+            ..UI::new(ctx, options)
         }
     }
 }
@@ -257,14 +402,33 @@ impl EventHandler for UI {
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        let (width, _) = graphics::size(ctx);
-        // Draw options
+        let (width, height) = graphics::size(ctx);
+        if self.overlay {
+            let dim = graphics::Mesh::new_rectangle(
+                ctx,
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(0.0, 0.0, width, height),
+                graphics::Color::new(0.0, 0.0, 0.0, 0.6),
+            )?;
+            graphics::draw(ctx, &dim, graphics::DrawParam::default())?;
+        }
+        // Draw options, the selected one highlighted
+        let selected_color = (0xe0, 0x56, 0x2c).into();
         let mut top = 0.0;
-        for opt in self.options.iter() {
-            match opt {
-                MenuEntry::Transition(sc, msg, _, _) | MenuEntry::Message(sc, msg) => {
-                    let frag = graphics::TextFragment::new(msg.clone());
-                    let frag = frag.scale(graphics::Scale::uniform(*sc)).font(self.font);
+        for line in self.menu.lines() {
+            match line {
+                menu::Line::Text {
+                    scale,
+                    text,
+                    selected,
+                } => {
+                    let frag = graphics::TextFragment::new(text);
+                    let frag = frag.scale(graphics::Scale::uniform(scale)).font(self.font);
+                    let frag = if selected {
+                        frag.color(selected_color)
+                    } else {
+                        frag
+                    };
                     let text = graphics::Text::new(frag);
                     let (w, y) = text.dimensions(ctx);
 
@@ -277,13 +441,12 @@ impl EventHandler for UI {
                     )?;
                     top += y as f32;
                 }
-                MenuEntry::VSpace(y) => {
-                    top += *y as f32;
+                menu::Line::Space(y) => {
+                    top += y;
                 }
             };
         }
-
-        graphics::present(ctx)
+        Ok(())
     }
 
     fn key_down_event(
@@ -293,16 +456,13 @@ impl EventHandler for UI {
         _keymods: KeyMods,
         _repeat: bool,
     ) {
-        // Check if the pressed char is part of the menu
-        for opt in self.options.iter() {
-            if let MenuEntry::Transition(_, _, k, state) = opt {
-                if *k == keycode {
-                    self.goto_state = Some(state.clone());
-                    break;
-                }
-            }
-        }
+        self.goto_state = self.menu.key(keycode);
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, ch: char) {
+        self.menu.text(ch);
     }
+
     fn quit_event(&mut self, _ctx: &mut Context) -> bool {
         println!("UI received quit event! Returning false to quit");
         false // Quitting
@@ -310,9 +470,24 @@ impl EventHandler for UI {
 }
 
 impl State for UI {
-    fn next_state(&mut self) -> Option<States> {
+    fn next_state(&mut self) -> Option<StateChange> {
         self.goto_state.take()
     }
+
+    /// Starts from the first entry, keeping the lines of a report or error in
+    /// the payload for Details entries
+    fn on_enter(&mut self, _ctx: &mut Context, payload: Payload) {
+        self.menu.reset();
+        self.menu.set_details(match payload {
+            Payload::Report(report) => report.to_string().lines().map(String::from).collect(),
+            Payload::Error(e) => vec![e],
+            _ => Vec::new(),
+        });
+    }
+
+    fn is_overlay(&self) -> bool {
+        self.overlay
+    }
 }
 
 fn random_enemy_position(width: f32) -> f32 {
@@ -320,16 +495,37 @@ fn random_enemy_position(width: f32) -> f32 {
     rng.gen_range(width * 0.1, width * 0.9) as f32
 }
 
+/// Reads a word producer from a course
+pub type CourseLoader<P> = Box<dyn Fn(&Course) -> Result<P, String>>;
+
 pub struct Game<P: WordProducer> {
-    goto_state: Option<States>,
+    goto_state: Option<StateChange>,
     level: usize,
+    // Last level played to its end, which Retry plays again and Next follows
+    played: usize,
     time: std::time::Instant,
+    // When the game was covered by another state (e.g. pause), if it is
+    paused: Option<std::time::Instant>,
+    // When the level being played was started, and how it is being typed
+    started: std::time::Instant,
+    stats: Stats,
     players: Vec<Player>,
     enemies: Vec<Enemy>,
     target: Option<Enemy>, // The current enemy being targeted
     sequence: usize,       // Where do we start?
     producer: P,
-    font_size: f32,
+    // Course played, and how to read a fresh producer from a course
+    course: Option<Course>,
+    load_course: Option<CourseLoader<P>>,
+    settings: Shared<Settings>,
+    // Difficulty preset chosen, by its index in Preset::ALL, and its values
+    preset: Shared<usize>,
+    difficulty: Difficulty,
+    // Pace following the player, when the difficulty is dynamic
+    adaptive: Option<Adaptive>,
+    keys: Keymap<GameAction>,
+    // Name of the player, as changed in menus
+    player_name: Option<Shared<String>>,
     //background: graphics::Image,
 }
 
@@ -339,7 +535,11 @@ impl<P: WordProducer> Game<P> {
         Game {
             goto_state: None,
             level: 0,
+            played: 0,
             time: std::time::Instant::now(),
+            paused: None,
+            started: std::time::Instant::now(),
+            stats: Stats::default(),
             players: vec![Player {
                 pos_x: 300.0,
                 pos_y: 700.0,
@@ -350,50 +550,195 @@ impl<P: WordProducer> Game<P> {
             target: None,
             sequence: 0,
             producer: word_producer,
-            font_size,
+            course: None,
+            load_course: None,
+            settings: menu::shared(Settings {
+                font_size,
+                ..Settings::default()
+            }),
+            preset: menu::shared(Preset::Normal.index()),
+            difficulty: Difficulty::default(),
+            adaptive: None,
+            keys: Keymap::default(),
+            player_name: None,
             //background: graphics::Image::new(ctx, "/background.png")
             //    .expect("Cannot load background"),
         }
     }
+
+    /// Replaces the default key bindings
+    pub fn set_keys(&mut self, keys: Keymap<GameAction>) {
+        self.keys = keys;
+    }
+
+    /// Shares the settings, which are read while playing
+    pub fn set_settings(&mut self, settings: Shared<Settings>) {
+        self.settings = settings;
+    }
+
+    /// Shares the difficulty preset, by its index in Preset::ALL, which is
+    /// read at every new game
+    pub fn set_difficulty(&mut self, preset: Shared<usize>) {
+        self.preset = preset;
+    }
+
+    /// Shares the name of the player, which is read at every new game
+    pub fn set_player_name(&mut self, name: Shared<String>) {
+        self.player_name = Some(name);
+    }
+
+    /// Sets the course being played, and how to read a producer from a course,
+    /// so that every new game gets a fresh producer and the course can be
+    /// changed
+    pub fn set_course<F>(&mut self, course: Course, load_course: F)
+    where
+        F: Fn(&Course) -> Result<P, String> + 'static,
+    {
+        self.course = Some(course);
+        self.load_course = Some(Box::new(load_course));
+    }
+
+    /// Hits the target, or picks an enemy to attack, with a character typed
+    /// or a key token
+    fn type_char(&mut self, ch: char) {
+        let (hits, missed) = (self.stats.hits, self.stats.missed());
+        if let Some(enemy) = self.target.as_mut() {
+            // There's a current target, hit that one
+            if let Some(c) = enemy.word.chars().next() {
+                // Make sure input is valid
+                if c == ch {
+                    enemy.word.remove(0);
+                    self.stats.hit();
+                } else {
+                    self.stats.miss(c);
+                    // TODO Show user error (e.g. color enemy)
+                    // TODO "correction mode": when input is wrong, add it to enemy making it
+                    // stronger
+                }
+            }
+        } else {
+            // Pick enemy to attack
+            let mut i = 0;
+            while i != self.enemies.len() {
+                if let Some(c) = self.enemies[i].word.chars().next() {
+                    if c == ch {
+                        let mut enemy = self.enemies.remove(i);
+                        enemy.word.remove(0);
+                        self.target = Some(enemy);
+                        self.stats.hit();
+                        break;
+                    }
+                }
+                i += 1;
+            }
+            // No enemy starts with the character typed
+            if self.target.is_none() && !self.enemies.is_empty() {
+                self.stats.miss(ch);
+            }
+        }
+        // Keys counted in stats tell the dynamic difficulty how the player is doing
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            let now = std::time::Instant::now();
+            if self.stats.hits > hits {
+                adaptive.key(now, true);
+            } else if self.stats.missed() > missed {
+                adaptive.key(now, false);
+            }
+        }
+        // The old enemy might have been killer, as well as 1-char enemies
+        if let Some(enemy) = self.target.as_mut() {
+            if enemy.word.is_empty() {
+                self.target = None;
+                // TODO destroy animation? How?
+                // self.players[0].word.clear();
+            }
+        }
+    }
+
+    /// True if the token of a key is the next character to type
+    fn wants_token(&self, token: char) -> bool {
+        wants_token(&self.enemies, self.target.as_ref(), token)
+    }
+}
+
+/// True if the token is next in the target or, without one, first in a word on
+/// screen, as type_char matches characters
+fn wants_token(enemies: &[Enemy], target: Option<&Enemy>, token: char) -> bool {
+    let next = |en: &Enemy| en.word.starts_with(token);
+    match target {
+        Some(en) => next(en),
+        None => enemies.iter().any(next),
+    }
 }
 
 impl<P: WordProducer> EventHandler for Game<P> {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         // Move enemies a bit towards me
+        let pace = self.adaptive.as_ref().map_or(1.0, |a| a.pace);
         for en in &mut self.enemies {
-            en.pos_y += en.speed * (1 + self.level) as f32;
+            en.pos_y += en.speed * pace;
         }
         if let Some(en) = self.target.as_mut() {
-            en.pos_y += en.speed * (1 + self.level) as f32;
+            en.pos_y += en.speed * pace;
+        }
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            // How far down the screen is the enemy closest to me
+            let (_, height) = graphics::size(ctx);
+            let lowest = self
+                .enemies
+                .iter()
+                .chain(self.target.iter())
+                .map(|en| en.pos_y)
+                .fold(0.0, f32::max);
+            adaptive.adjust(std::time::Instant::now(), lowest / height);
         }
 
         match self.producer.next_word(self.level, self.sequence) {
             Enemies::GameComplete => {
                 if self.enemies.is_empty() && self.target.is_none() {
-                    self.goto_state = Some(States::Victory);
+                    self.goto_state = Some(States::Victory.into());
                 } else {
                     // There are still enemies to kill...
                 }
             }
             Enemies::LevelComplete => {
                 if self.enemies.is_empty() && self.target.is_none() {
-                    self.level += 1;
-                    self.sequence = 0;
+                    // Show how the level went, before the next one if any
+                    self.played = self.level;
+                    self.stats.time = self.started.elapsed();
+                    let report = LevelReport {
+                        stats: self.stats.clone(),
+                        next: self.producer.lesson(self.level + 1),
+                    };
+                    let state = match self.producer.next_word(self.level + 1, 0) {
+                        Enemies::GameComplete => States::Victory,
+                        _ => States::NextLevel,
+                    };
+                    let payload = Payload::Report(Box::new(report));
+                    self.goto_state = Some(StateChange::Switch(state, payload));
                 } else {
                     // There are still enemies to kill...
                 }
             }
             Enemies::Some(word) => {
-                // Add enemies if none is present or enough time passed
-                if self.time.elapsed().as_secs() >= 2 || self.enemies.is_empty() {
+                // Add enemies if none is present or enough time passed, and
+                // there are not too many already
+                let spawn_secs = self.difficulty.spawn_secs(self.level) / pace;
+                let on_screen = self.enemies.len() + self.target.iter().count();
+                if (self.time.elapsed().as_secs_f32() >= spawn_secs || self.enemies.is_empty())
+                    && on_screen < self.difficulty.max_enemies
+                {
                     //let elap = self.start.elapsed().as_millis();
                     let (width, _) = graphics::size(ctx);
+                    if let Some(adaptive) = self.adaptive.as_mut() {
+                        adaptive.word(std::time::Instant::now(), word.chars().count());
+                    }
                     self.enemies.push(Enemy {
                         pos_x: random_enemy_position(width),
                         pos_y: -1.0,
-                        speed: 0.5,
+                        speed: self.difficulty.speed(self.level, word.chars().count()),
                         word,
-                        font_size: self.font_size,
+                        font_size: self.settings.borrow().font_size,
                     });
                     self.time = std::time::Instant::now();
                     self.sequence += 1;
@@ -407,9 +752,10 @@ impl<P: WordProducer> EventHandler for Game<P> {
         // graphics::clear(ctx, graphics::BLACK);
         //graphics::draw(ctx, &self.background, graphics::DrawParam::default())?;
 
-        let enemy_color = (0xd2, 0xd5, 0x3b).into();
-        let target_color = (0xe0, 0x56, 0x2c).into();
-        let player_color = (0xff, 0x00, 0xff).into();
+        let settings = self.settings.borrow();
+        let enemy_color = settings.enemy_color;
+        let target_color = settings.target_color;
+        let player_color = settings.player_color;
 
         let (width, height) = graphics::size(ctx);
 
@@ -427,15 +773,21 @@ impl<P: WordProducer> EventHandler for Game<P> {
             pl.draw(ctx, player_color)?;
         }
 
-        let lvl = graphics::Text::new(format!("Level {}", self.level));
+        let lvl = match &self.adaptive {
+            Some(adaptive) => graphics::Text::new(format!(
+                "{:.0} WPM | pace {:.2} | Level {}",
+                adaptive.wpm(std::time::Instant::now()),
+                adaptive.pace,
+                self.level
+            )),
+            None => graphics::Text::new(format!("Level {}", self.level)),
+        };
         let (w, h) = lvl.dimensions(ctx);
         graphics::draw(
             ctx,
             &lvl,
             (na::Point2::new(width - w as f32, height - h as f32),),
-        )?;
-
-        graphics::present(ctx)
+        )
     }
 
     fn text_input_event(&mut self, _ctx: &mut Context, ch: char) {
@@ -449,342 +801,1642 @@ impl<P: WordProducer> EventHandler for Game<P> {
         }
         */
 
-        if let Some(enemy) = self.target.as_mut() {
-            // There's a current target, hit that one
-            if let Some(c) = enemy.word.chars().next() {
-                // Make sure input is valid
-                if c == ch {
-                    enemy.word.remove(0);
-                } else {
-                    // TODO Show user error (e.g. color enemy)
-                    // TODO "correction mode": when input is wrong, add it to enemy making it
-                    // stronger
-                }
-            }
-        } else {
-            // Pick enemy to attack
-            let mut i = 0;
-            while i != self.enemies.len() {
-                if let Some(c) = self.enemies[i].word.chars().next() {
-                    if c == ch {
-                        let mut enemy = self.enemies.remove(i);
-                        enemy.word.remove(0);
-                        self.target = Some(enemy);
-                        break;
-                    }
-                }
-                i += 1;
-            }
-        }
-        // The old enemy might have been killer, as well as 1-char enemies
-        if let Some(enemy) = self.target.as_mut() {
-            if enemy.word.is_empty() {
-                self.target = None;
-                // TODO destroy animation? How?
-                // self.players[0].word.clear();
-            }
+        if ch.is_control() {
+            return;
         }
+        self.type_char(ch);
     }
     fn key_down_event(
         &mut self,
         _ctx: &mut Context,
         keycode: KeyCode,
-        _keymods: KeyMods,
-        _repeat: bool,
+        keymods: KeyMods,
+        repeat: bool,
     ) {
         //println!("KEY DOWN {:?}", keycode);
+        // Keys typing no character, like Enter, type their tokens while a word
+        // on screen has them, so Escape pauses unless it's being practised
+        if let Some(token) = wording::key_token(keycode) {
+            if !repeat && self.wants_token(token) {
+                self.type_char(token);
+                return;
+            }
+        }
         // When Escape is pressed, don't quit the app immediately (default key_down_event), but
         // go to pause state
-        if keycode == KeyCode::Escape {
-            self.goto_state = Some(States::Pause);
+        if self.keys.action(keycode, keymods) == Some(GameAction::Pause) {
+            self.goto_state = Some(StateChange::Push(States::Pause, Payload::None));
         }
     }
 }
 
 impl<P: WordProducer> State for Game<P> {
-    fn next_state(&mut self) -> Option<States> {
+    fn next_state(&mut self) -> Option<StateChange> {
         self.goto_state.take()
     }
+
+    /// Starts a new game with a fresh producer, from the level in payload or
+    /// the first one
+    fn on_enter(&mut self, ctx: &mut Context, payload: Payload) {
+        let course = match &payload {
+            Payload::Course(course, _) => Some(course.clone()),
+            _ => self.course.clone(),
+        };
+        if let (Some(course), Some(load_course)) = (course, &self.load_course) {
+            match load_course(&course) {
+                Ok(producer) => {
+                    self.producer = producer;
+                    self.course = Some(course);
+                }
+                Err(e) => {
+                    // Back to the choice of the course, rather than playing another one
+                    let e = format!("Unable to load course {}: {}", course, e);
+                    self.goto_state = Some(StateChange::Switch(States::Courses, Payload::Error(e)));
+                    return;
+                }
+            }
+        }
+        let next = payload == Payload::Next;
+        self.level = match payload {
+            Payload::Level(level) => level,
+            Payload::Retry => self.played,
+            Payload::Next => self.played + 1,
+            Payload::Course(_, level) => level,
+            Payload::None | Payload::Report(_) | Payload::Error(_) => 0,
+        };
+        self.sequence = 0;
+        self.enemies.clear();
+        self.target = None;
+        self.time = std::time::Instant::now();
+        self.paused = None;
+        self.started = std::time::Instant::now();
+        self.stats = Stats::default();
+        if let Some(name) = &self.player_name {
+            self.players[0].name = name.borrow().clone();
+        }
+        // The window might have another size than the one the player was placed for
+        let (width, height) = graphics::size(ctx);
+        let player = &mut self.players[0];
+        player.pos_x = width * 0.5;
+        player.pos_y = height - 100.0;
+        player.font_size = self.settings.borrow().font_size;
+        let preset = Preset::ALL[*self.preset.borrow()];
+        let settings = self.settings.borrow();
+        self.difficulty = settings.difficulty_of(preset);
+        // The pace is kept going on to the next level
+        self.adaptive = match self.adaptive.take() {
+            Some(adaptive) if settings.dynamic && next => Some(adaptive),
+            _ if settings.dynamic => Some(Adaptive::new(
+                settings.target_accuracy,
+                settings.target_pressure,
+            )),
+            _ => None,
+        };
+    }
+
+    /// Stops the clocks of the game, which is not updated while covered
+    fn on_cover(&mut self, _ctx: &mut Context) {
+        self.paused = Some(std::time::Instant::now());
+    }
+
+    /// Restarts the clocks, as if no time passed while covered
+    fn on_resume(&mut self, _ctx: &mut Context, _payload: Payload) {
+        if let Some(paused) = self.paused.take() {
+            let paused = paused.elapsed();
+            self.time += paused;
+            self.started += paused;
+            if let Some(adaptive) = self.adaptive.as_mut() {
+                adaptive.shift(paused);
+            }
+        }
+    }
+}
+
+/// Seconds between autosaves of unsaved edits in Paint
+const AUTOSAVE_SECS: u64 = 30;
+/// Clicks closer than this (in pixels) to the last one are on the same spot
+const PICK_DIST: f32 = 3.0;
+/// Length (in pixels) of each segment on the rim of ellipses
+const ELLIPSE_SEGMENT: f32 = 8.0;
+/// Height of a row in the palette panel, and width of its swatches
+const PANEL_ROW: f32 = 22.0;
+/// Width of the palette panel
+const PANEL_WIDTH: f32 = 200.0;
+/// Smallest spacing of the grid, in pixels
+const MIN_GRID: f32 = 1.0;
+/// Zoom factor for each step of mouse wheel, or press of I and O
+const ZOOM_STEP: f32 = 1.25;
+/// Angle (in degrees) that lines are constrained to multiples of, with Shift
+const ANGLE_STEP: f32 = 15.0;
+/// Width of lines of wireframes and shapes being drawn, in pixels
+const LINE_WIDTH: f32 = 1.0;
+/// Side of the squares marking vertices in wireframes, in pixels
+const MARKER_SIZE: f32 = 4.0;
+/// Seconds a message stays on the HUD of Paint
+const MESSAGE_SECS: u64 = 4;
+/// Mouse bindings of Paint, shown by the help overlay above the keys
+const PAINT_MOUSE_HELP: &str = "\
+Mouse
+  Left click        add a point, or select with the select tool
+  Middle drag       pan the view
+  Wheel             zoom the view about the cursor
+  Shift             constrain angles (circle with the ellipse tool)
+
+Keys";
+
+/// Path of the sidecar file used to autosave edits to filename
+fn autosave_path(filename: &str) -> String {
+    format!("{}.autosave", filename)
+}
+
+/// True if file a exists and was modified after file b (or b does not exist)
+fn is_newer(a: &str, b: &str) -> bool {
+    let modified = |p: &str| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(a), modified(b)) {
+        (Some(ta), Some(tb)) => ta > tb,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+/// Writes data to a temporary file next to path, then renames it over path,
+/// so that a crash while writing never leaves a truncated file behind
+pub fn write_atomic(path: &str, data: &str) -> std::io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut f = std::fs::File::create(&tmp)?;
+    f.write_all(data.as_bytes())?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Tools used by mouse clicks in Paint
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tool {
+    /// Every three clicks a triangle is added
+    Triangle,
+    /// Clicks add points to a polygon, that is filled with triangles when
+    /// closed by clicking its first point again (or pressing Enter)
+    Polygon,
+    /// A click sets the center and another one a corner of the box around an
+    /// ellipse (a circle, holding Shift), that is filled with triangles
+    Ellipse,
+    /// Clicks select triangles, that can be dragged around
+    Select,
+}
+
+/// How triangles of a color are shown and edited in Paint
+#[derive(Debug, Clone)]
+struct Layer {
+    /// Name shown in the palette panel
+    name: String,
+    /// Triangles are not drawn
+    hidden: bool,
+    /// Triangles can't be changed
+    locked: bool,
+}
+
+impl Layer {
+    fn new(name: String) -> Self {
+        Layer {
+            name,
+            hidden: false,
+            locked: false,
+        }
+    }
+}
+
+/// Maps scene coordinates to the screen in Paint, to pan and zoom the view
+/// without changing triangles
+#[derive(Debug, Clone, Copy)]
+struct Camera {
+    /// Screen position of the scene origin
+    offset: na::Vector2<f32>,
+    /// Screen pixels for each scene unit
+    zoom: f32,
+}
+
+impl Camera {
+    /// A camera showing the scene with its origin at the margin
+    fn new(margin: f32) -> Self {
+        Camera {
+            offset: na::Vector2::new(margin, margin),
+            zoom: 1.0,
+        }
+    }
+
+    /// Screen position of scene point p
+    fn screen_of(&self, p: &na::Point2<f32>) -> na::Point2<f32> {
+        na::Point2::from(p.coords * self.zoom + self.offset)
+    }
+
+    /// Scene point at screen position p
+    fn scene_of(&self, p: &na::Point2<f32>) -> na::Point2<f32> {
+        na::Point2::from((p.coords - self.offset) / self.zoom)
+    }
+
+    /// Zooms by factor, keeping still the scene point at screen position p
+    fn zoom_at(&mut self, p: &na::Point2<f32>, factor: f32) {
+        let s = self.scene_of(p);
+        self.zoom = (self.zoom * factor).clamp(0.05, 50.0);
+        self.offset = p.coords - s.coords * self.zoom;
+    }
+}
+
+/// What the cursor snapped to in Paint
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Snap {
+    /// An existing vertex
+    Vertex,
+    /// The closest point on an edge of a triangle
+    Edge,
+    /// A point of the grid
+    Grid,
+    /// A line from the last point at a multiple of ANGLE_STEP
+    Angle,
+}
+
+impl Snap {
+    /// Color of the marker on the snap target
+    fn color(self) -> graphics::Color {
+        match self {
+            Snap::Vertex => graphics::WHITE,
+            Snap::Edge => graphics::Color::from_rgb(255, 220, 0),
+            Snap::Grid => graphics::Color::from_rgb(0, 200, 255),
+            Snap::Angle => graphics::Color::from_rgb(255, 0, 200),
+        }
+    }
+}
+
+/// What is being dragged with the mouse in Paint
+enum Drag {
+    /// Selected triangles, with the last mouse position and whether they
+    /// were detached from the vertices shared with other triangles
+    Selection(na::Point2<f32>, bool),
+    /// A vertex, moving every triangle using it
+    Vertex(usize),
+    /// A rubber band rectangle, with its first corner
+    Band(na::Point2<f32>),
+    /// The view, with the last screen position of the cursor
+    Pan(na::Point2<f32>),
 }
 
 pub struct Paint {
-    // Colored triangles used to draw
-    ct: ColoredTriangles,
+    // Colored triangles used to draw, sharing vertices
+    mesh: IndexedTriangles,
     // Current color (index) for triangles being built
     cur_color: usize,
     // Triangle being built
     poly: Vec<na::Point2<f32>>,
     // Current mouse position
     m_pos: na::Point2<f32>,
-    // An optional background image
+    // An optional background image, used as reference to trace over
     background: Option<graphics::Image>,
+    // If the background is drawn, and how opaque it is
+    show_background: bool,
+    background_opacity: f32,
     // Whether to normalize upon save
     write_absolute: bool,
     // Margin of drawing area
     margin: f32,
+    // View on the scene, used to draw and to map the cursor
+    camera: Camera,
     // Snap distance
     snap_dist: f32,
     // Snap enabled
     use_snap: bool,
+    // Snap to edges enabled
+    snap_edges: bool,
+    // Snap to grid enabled, and its spacing
+    use_grid: bool,
+    grid: f32,
     // Draw with wireframes
     wireframe: bool,
     // File to read and write
     filename: String,
+    // Whether there are edits that were not saved to file
+    dirty: bool,
+    // Last time unsaved edits were written to the autosave file
+    last_autosave: std::time::Instant,
+    // Set when quitting was requested with unsaved edits, waiting for an answer
+    quit_prompt: bool,
+    // Tool used by mouse clicks
+    tool: Tool,
+    // Selected triangles, as (color index, triangle index)
+    selection: Vec<(usize, usize)>,
+    // What is being dragged, if anything
+    drag: Option<Drag>,
+    // Where the last triangle was picked, and how many triangles above it
+    pick: Option<(na::Point2<f32>, usize)>,
+    // Copied triangles, as (color index, vertices)
+    clipboard: Vec<(usize, Vec<na::Point2<f32>>)>,
+    // Names and state of colors, one for each color in mesh
+    layers: Vec<Layer>,
+    // Palette panel is drawn
+    show_palette: bool,
+    // Text typed for a new color, while asking for it
+    color_prompt: Option<String>,
+    // Last message shown on the HUD, and when it was shown
+    message: Option<(String, std::time::Instant)>,
+    // Help overlay is drawn
+    show_help: bool,
+    // Bindings from keys to actions
+    keys: Keymap<PaintAction>,
 }
 
 impl Paint {
-    pub fn new(ctx: &mut Context, margin: f32, colors: Vec<String>, filename: String, read_absolute: bool, write_absolute: bool) -> Self {
+    /// Opens the scene of a file (an empty one if it can't be read), failing if
+    /// it's not a scene
+    pub fn new(
+        ctx: &mut Context,
+        margin: f32,
+        colors: Vec<graphics::Color>,
+        filename: String,
+        read_absolute: bool,
+        write_absolute: bool,
+    ) -> Result<Self, SceneError> {
+        // If a previous session left an autosave newer than the file, the
+        // edits were never saved (e.g. a crash), so recover them
+        let recover = !filename.is_empty() && is_newer(&autosave_path(&filename), &filename);
         // Read file as string to build colored triangles
         let data = if filename.is_empty() {
             "".to_owned()
+        } else if recover {
+            println!("Recovering unsaved edits from {}", autosave_path(&filename));
+            std::fs::read_to_string(autosave_path(&filename)).unwrap_or_else(|_| "".to_owned())
         } else {
             std::fs::read_to_string(&filename).unwrap_or_else(|_| "".to_owned())
         };
-        let mut ct: ColoredTriangles = data.as_str().into();
-        // Scale data if reading is relative
-        if !read_absolute {
+        let mut ct: ColoredTriangles = data.parse()?;
+        // Scale data if reading is relative, autosaves are always relative
+        if !read_absolute || recover {
             let (width, height) = graphics::size(ctx);
             ct.scale(width - 2.0 * margin, height - 2.0 * margin);
         }
         // Add colors to ct, if some were missing
-        colors.iter().for_each(|c| ct.add_color(c));
-
-        // TODO load background as reference
-        //let background =
-        //    graphics::Image::new(ctx, "/background.png").expect("Cannot find background.png");
+        colors.iter().for_each(|&c| ct.add_color(c));
+        let mesh = IndexedTriangles::from(&ct);
+        let layers = mesh
+            .colors
+            .iter()
+            .map(|&c| Layer::new(col2str(c)))
+            .collect();
 
-        Paint {
-            ct,
+        Ok(Paint {
+            mesh,
             poly: vec![],
             cur_color: 0,
             m_pos: na::Point2::new(0.0, 0.0),
-            background: None, //Some(background),
+            background: None,
+            show_background: true,
+            background_opacity: 0.5,
             snap_dist: 10.0,
             use_snap: true,
+            snap_edges: false,
+            use_grid: false,
+            grid: 20.0,
             filename,
             wireframe: false,
             margin,
+            camera: Camera::new(margin),
             write_absolute,
-        }
+            dirty: recover,
+            last_autosave: std::time::Instant::now(),
+            quit_prompt: false,
+            tool: Tool::Triangle,
+            selection: vec![],
+            drag: None,
+            pick: None,
+            clipboard: vec![],
+            layers,
+            show_palette: true,
+            color_prompt: None,
+            message: None,
+            show_help: false,
+            keys: Keymap::default(),
+        })
     }
 
-    /// Shift points according to margin
-    fn mshift(&self, points: &[na::Point2<f32>]) -> Vec<na::Point2<f32>> {
-        // Compute margin vector
-        let margin = na::Point2::new(self.margin, self.margin).coords;
-        // Offset each point by margin
-        points.iter().map(|p| p + margin).collect::<Vec<_>>()
+    /// Replaces the default key bindings
+    pub fn set_keys(&mut self, keys: Keymap<PaintAction>) {
+        self.keys = keys;
     }
-}
 
-impl EventHandler for Paint {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+    /// Loads an image (e.g. PNG or JPEG) to draw as reference below the triangles,
+    /// stretched over the drawing area
+    pub fn set_reference(
+        &mut self,
+        ctx: &mut Context,
+        path: &std::path::Path,
+        opacity: f32,
+    ) -> GameResult<()> {
+        // Images are read from ggez filesystem, so the image folder must be there
+        let path = path.canonicalize()?;
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, name),
+            _ => {
+                return Err(ggez::GameError::ResourceNotFound(
+                    path.display().to_string(),
+                    vec![],
+                ))
+            }
+        };
+        ggez::filesystem::mount(ctx, dir, true);
+        let img = graphics::Image::new(ctx, std::path::Path::new("/").join(name))?;
+        self.background = Some(img);
+        self.background_opacity = opacity.clamp(0.0, 1.0);
         Ok(())
     }
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        if let Some(img) = &self.background {
-            graphics::draw(
-                ctx,
-                img,
-                graphics::DrawParam::default().dest(na::Point2::new(self.margin, self.margin)),
-            )?;
-        } else {
-            graphics::clear(ctx, graphics::BLACK);
+
+    /// Adds colors of palette missing from the layers, naming them
+    pub fn add_palette(&mut self, palette: &Palette) {
+        for (color, name) in &palette.colors {
+            let c = self.add_color(*color);
+            if !name.is_empty() {
+                self.layers[c].name = name.clone();
+            }
         }
+    }
 
-        let mut builder = graphics::MeshBuilder::new();
-        for i in 0..self.ct.colors.len() {
-            if !self.ct.triangles[i].is_empty() {
-                // Offset triangle points by margin
-                let tri = self.mshift(&self.ct.triangles[i]);
-                if self.wireframe {
-                    builder.polyline(graphics::DrawMode::stroke(1.0), &tri, self.ct.colors[i])?;
-                } else {
-                    builder.triangles(&tri, self.ct.colors[i])?;
-                }
+    /// Adds a layer with color on top of the others, unless there is one
+    /// already, returning its index
+    fn add_color(&mut self, color: graphics::Color) -> usize {
+        match self.mesh.colors.iter().position(|&c| c == color) {
+            Some(c) => c,
+            None => {
+                self.layers.push(Layer::new(col2str(color)));
+                self.dirty = true;
+                self.mesh.add_color(color)
             }
         }
-        match self.poly.len() {
-            1 => {
-                let mut poly = self.poly.clone();
-                poly.push(self.m_pos);
-                // Lines and polylines are crashing right now, so I'm making
-                // a small triangle to simulate that
-                poly.push(na::Point2::new(poly[0].x, poly[0].y - 5.0));
-                // Offset poly by margin
-                let poly = self.mshift(&poly);
-                if self.wireframe {
-                    builder.polyline(
-                        graphics::DrawMode::stroke(1.0),
-                        &poly,
-                        self.ct.colors[self.cur_color],
-                    )?;
+    }
+
+    /// True if triangles of color index c can be seen and changed
+    fn editable(&self, c: usize) -> bool {
+        !self.layers[c].hidden && !self.layers[c].locked
+    }
+
+    /// Changes color indices of selection and clipboard, dropping what is mapped to None
+    fn remap_layers<F: Fn(usize) -> Option<usize>>(&mut self, f: F) {
+        self.selection = self
+            .selection
+            .iter()
+            .filter_map(|&(c, i)| f(c).map(|c| (c, i)))
+            .collect();
+        self.clipboard = self
+            .clipboard
+            .drain(..)
+            .filter_map(|(c, t)| f(c).map(|c| (c, t)))
+            .collect();
+    }
+
+    /// Drops triangles of color index c from the selection, keeping the clipboard
+    fn deselect_color(&mut self, c: usize) {
+        self.selection.retain(|&(s, _)| s != c);
+    }
+
+    /// Forgets the vertex being dragged and the triangle picked, whose indices
+    /// change when triangles are removed
+    fn forget_indices(&mut self) {
+        if let Some(Drag::Vertex(_)) = self.drag {
+            self.drag = None;
+        }
+        self.pick = None;
+    }
+
+    /// Removes the current layer and its triangles, if it's not the last one
+    fn remove_layer(&mut self) {
+        let cur = self.cur_color;
+        if self.layers.len() == 1 {
+            self.notify("Unable to remove the only color");
+            return;
+        }
+        self.notify(format!("Removing color {}", self.layers[cur].name));
+        self.mesh.remove_color(cur);
+        self.forget_indices();
+        self.layers.remove(cur);
+        self.remap_layers(|c| match c {
+            c if c == cur => None,
+            c if c > cur => Some(c - 1),
+            c => Some(c),
+        });
+        self.cur_color = cur.min(self.layers.len() - 1);
+        self.dirty = true;
+    }
+
+    /// Moves the current layer over the next one (up) or below the previous one
+    fn move_layer(&mut self, up: bool) {
+        let cur = self.cur_color;
+        let other = match up {
+            true if cur + 1 < self.layers.len() => cur + 1,
+            false if cur > 0 => cur - 1,
+            _ => return,
+        };
+        self.mesh.swap_colors(cur, other);
+        self.layers.swap(cur, other);
+        self.remap_layers(|c| match c {
+            c if c == cur => Some(other),
+            c if c == other => Some(cur),
+            c => Some(c),
+        });
+        self.cur_color = other;
+        self.dirty = true;
+    }
+
+    /// Changes the alpha of the current color, unless another layer has the resulting color
+    fn change_alpha(&mut self, delta: f32) {
+        let mut color = self.mesh.colors[self.cur_color];
+        color.a = (color.a + delta).clamp(0.0, 1.0);
+        if self.mesh.colors.contains(&color) {
+            return;
+        }
+        self.mesh.colors[self.cur_color] = color;
+        self.dirty = true;
+    }
+
+    /// Prints a message, showing it on the HUD for a while
+    fn notify<S: Into<String>>(&mut self, msg: S) {
+        self.message = Some((msg.into(), std::time::Instant::now()));
+    }
+
+    /// Draws the state of painting at the bottom of the screen, and the last message
+    fn draw_hud(&self, ctx: &mut Context) -> GameResult<()> {
+        let (width, height) = graphics::size(ctx);
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        let grid = if self.use_grid {
+            format!("{}", self.grid)
+        } else {
+            "off".to_owned()
+        };
+        let total: usize = self.mesh.triangles.iter().map(|t| t.len()).sum();
+        let current = self.mesh.triangles[self.cur_color].len();
+        let help = self
+            .keys
+            .bindings(PaintAction::Help)
+            .first()
+            .map_or("no".to_owned(), |b| b.to_string());
+        // Normalized coordinates are the ones written to file, if not absolute
+        let normalized = na::Point2::new(
+            self.m_pos.x / (width - 2.0 * self.margin),
+            self.m_pos.y / (height - 2.0 * self.margin),
+        );
+        let mut lines = vec![
+            format!(
+                "{:?} tool | color {}/{} {} | snap {} {}px, edges {}, grid {} | wireframe {}",
+                self.tool,
+                self.cur_color + 1,
+                self.layers.len(),
+                self.layers[self.cur_color].name,
+                on_off(self.use_snap),
+                self.snap_dist,
+                on_off(self.snap_edges),
+                grid,
+                on_off(self.wireframe),
+            ),
+            format!(
+                "{}/{} triangles{} | cursor {:.1}, {:.1} | normalized {:.3}, {:.3} | zoom {:.0}% | {} help",
+                current,
+                total,
+                if self.dirty { " (unsaved)" } else { "" },
+                self.m_pos.x,
+                self.m_pos.y,
+                normalized.x,
+                normalized.y,
+                self.camera.zoom * 100.0,
+                help,
+            ),
+        ];
+        if let Some((msg, when)) = &self.message {
+            if when.elapsed().as_secs() < MESSAGE_SECS {
+                lines.push(msg.clone());
+            }
+        }
+        let text = graphics::Text::new(lines.join("\n"));
+        let (w, h) = text.dimensions(ctx);
+        let pos = na::Point2::new(self.margin, height - self.margin - h as f32);
+        let bg = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(pos.x - 2.0, pos.y - 2.0, w as f32 + 4.0, h as f32 + 4.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.7),
+        )?;
+        graphics::draw(ctx, &bg, graphics::DrawParam::default())?;
+        graphics::draw(ctx, &text, (pos,))
+    }
+
+    /// Draws the list of bindings at the center of the screen, in two columns
+    fn draw_help(&self, ctx: &mut Context) -> GameResult<()> {
+        let (width, height) = graphics::size(ctx);
+        let mut lines: Vec<String> = PAINT_MOUSE_HELP.lines().map(str::to_owned).collect();
+        lines.extend(self.keys.help());
+        let half = lines.len() - lines.len() / 2;
+        let left = graphics::Text::new(lines[..half].join("\n"));
+        let right = graphics::Text::new(lines[half..].join("\n"));
+        let (lw, lh) = left.dimensions(ctx);
+        let (rw, rh) = right.dimensions(ctx);
+        let (w, h) = ((lw + 20 + rw) as f32, lh.max(rh) as f32);
+        let pos = na::Point2::new((width - w) * 0.5, (height - h) * 0.5);
+        let bg = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(pos.x - 10.0, pos.y - 10.0, w + 20.0, h + 20.0),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.85),
+        )?;
+        graphics::draw(ctx, &bg, graphics::DrawParam::default())?;
+        graphics::draw(ctx, &left, (pos,))?;
+        graphics::draw(
+            ctx,
+            &right,
+            (na::Point2::new(pos.x + lw as f32 + 20.0, pos.y),),
+        )
+    }
+
+    /// Layer and column (swatch, hidden or locked flag, name) of the palette
+    /// panel at screen position x, y, if any
+    fn panel_at(&self, x: f32, y: f32) -> Option<(usize, usize)> {
+        let (x, y) = (x - self.margin, y - self.margin);
+        if !self.show_palette || x < 0.0 || y < 0.0 || x >= PANEL_WIDTH {
+            return None;
+        }
+        let row = (y / PANEL_ROW) as usize;
+        let col = ((x / PANEL_ROW) as usize).min(3);
+        // Layers on top are listed first
+        if row < self.layers.len() {
+            Some((self.layers.len() - 1 - row, col))
+        } else {
+            None
+        }
+    }
+
+    /// Draws the palette panel, with a row for each layer
+    fn draw_panel(&self, ctx: &mut Context) -> GameResult<()> {
+        let n = self.layers.len();
+        let rows = n + self.color_prompt.is_some() as usize;
+        let mut builder = graphics::MeshBuilder::new();
+        builder.rectangle(
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(
+                self.margin,
+                self.margin,
+                PANEL_WIDTH,
+                PANEL_ROW * rows as f32,
+            ),
+            graphics::Color::new(0.0, 0.0, 0.0, 0.7),
+        );
+        for c in 0..n {
+            let y = self.margin + PANEL_ROW * (n - 1 - c) as f32;
+            let swatch =
+                graphics::Rect::new(self.margin + 2.0, y + 2.0, PANEL_ROW - 4.0, PANEL_ROW - 4.0);
+            builder.rectangle(graphics::DrawMode::fill(), swatch, self.mesh.colors[c]);
+            if c == self.cur_color {
+                builder.rectangle(graphics::DrawMode::stroke(2.0), swatch, graphics::WHITE);
+            }
+        }
+        let mesh = builder.build(ctx)?;
+        graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
+
+        for (c, layer) in self.layers.iter().enumerate() {
+            let y = self.margin + PANEL_ROW * (n - 1 - c) as f32 + 4.0;
+            let texts = [
+                (1, if layer.hidden { "H" } else { "-" }.to_owned()),
+                (2, if layer.locked { "L" } else { "-" }.to_owned()),
+                // Name and number of triangles
+                (
+                    3,
+                    format!("{} ({})", layer.name, self.mesh.triangles[c].len()),
+                ),
+            ];
+            for (col, text) in texts.iter() {
+                let x = self.margin + PANEL_ROW * *col as f32 + 4.0;
+                graphics::draw(
+                    ctx,
+                    &graphics::Text::new(text.as_str()),
+                    (na::Point2::new(x, y),),
+                )?;
+            }
+        }
+        if let Some(color) = &self.color_prompt {
+            let y = self.margin + PANEL_ROW * n as f32 + 4.0;
+            let text = graphics::Text::new(format!("New color: {}_", color));
+            graphics::draw(ctx, &text, (na::Point2::new(self.margin + 4.0, y),))?;
+        }
+        Ok(())
+    }
+
+    /// Maps points from the scene to the screen, according to camera
+    fn mshift(&self, points: &[na::Point2<f32>]) -> Vec<na::Point2<f32>> {
+        points
+            .iter()
+            .map(|p| self.camera.screen_of(p))
+            .collect::<Vec<_>>()
+    }
+
+    /// Snap distance in scene units, as it's in pixels on screen
+    fn snap_radius(&self) -> f32 {
+        self.snap_dist / self.camera.zoom
+    }
+
+    /// Enables snapping to a grid with given spacing, at least a pixel
+    pub fn set_grid(&mut self, size: f32) {
+        self.grid = size.max(MIN_GRID);
+        self.use_grid = true;
+    }
+
+    /// Where point p snaps to, and what it snapped to, if anything. Vertices
+    /// come first, skipping the one given, then edges and then the grid. If
+    /// constrain, a line from the last point being drawn is kept at fixed angles
+    fn snap(
+        &self,
+        p: na::Point2<f32>,
+        skip: Option<usize>,
+        constrain: bool,
+    ) -> (na::Point2<f32>, Option<Snap>) {
+        if let (true, Some(last)) = (constrain, self.poly.last()) {
+            let d = p - last;
+            let step = ANGLE_STEP.to_radians();
+            let a = (d.y.atan2(d.x) / step).round() * step;
+            let dir = na::Vector2::new(a.cos(), a.sin());
+            return (last + dir * d.dot(&dir), Some(Snap::Angle));
+        }
+        let r = self.snap_radius();
+        if self.use_snap {
+            let nearest = self.mesh.nearest(2, &p);
+            if let Some(&(v, sqd)) = nearest.iter().find(|(v, _)| Some(*v) != skip) {
+                if sqd <= r * r {
+                    return (self.mesh.vertices[v], Some(Snap::Vertex));
+                }
+            }
+        }
+        if self.snap_edges {
+            let skip = skip.map_or(vec![], |v| vec![v]);
+            if let Some(q) = self.mesh.nearest_on_edge(&p, r, &skip) {
+                return (q, Some(Snap::Edge));
+            }
+        }
+        if self.use_grid {
+            let q = na::Point2::new(
+                (p.x / self.grid).round() * self.grid,
+                (p.y / self.grid).round() * self.grid,
+            );
+            return (q, Some(Snap::Grid));
+        }
+        (p, None)
+    }
+
+    /// Adds lines of the grid covering the screen to builder, if they are not too dense
+    fn build_grid(&self, ctx: &Context, builder: &mut graphics::MeshBuilder) {
+        let step = self.grid * self.camera.zoom;
+        if step < 4.0 {
+            return;
+        }
+        let (width, height) = graphics::size(ctx);
+        let color = graphics::Color::new(1.0, 1.0, 1.0, 0.15);
+        // Screen position of the first line, on each axis
+        let origin = self.camera.screen_of(&na::Point2::origin());
+        let (x0, y0) = (origin.x.rem_euclid(step), origin.y.rem_euclid(step));
+        for k in 0..=(width / step) as usize {
+            let x = x0 + step * k as f32;
+            builder.rectangle(
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(x, 0.0, 1.0, height),
+                color,
+            );
+        }
+        for k in 0..=(height / step) as usize {
+            let y = y0 + step * k as f32;
+            builder.rectangle(
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(0.0, y, width, 1.0),
+                color,
+            );
+        }
+    }
+
+    /// Copy of the triangles as they should be written, normalized if not absolute
+    fn output_triangles(&self, ctx: &Context, absolute: bool) -> ColoredTriangles {
+        let mut ct = ColoredTriangles::from(&self.mesh);
+        if !absolute {
+            let (width, height) = graphics::size(ctx);
+            let width = width - self.margin * 2.0;
+            let height = height - self.margin * 2.0;
+            ct.scale(1.0 / width, 1.0 / height);
+        }
+        ct
+    }
+
+    /// Writes triangles to file and drops the autosave, which is now stale
+    fn save(&mut self, ctx: &Context) -> std::io::Result<()> {
+        if self.filename.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no file to save to",
+            ));
+        }
+        self.notify(format!(
+            "Saving list of triangles by color over file {}, absolute coords: {}",
+            self.filename, self.write_absolute
+        ));
+        let ct = self.output_triangles(ctx, self.write_absolute);
+        write_atomic(&self.filename, &ct.to_string())?;
+        self.dirty = false;
+        // The autosave might not exist, nothing to do in that case
+        let _ = std::fs::remove_file(autosave_path(&self.filename));
+        Ok(())
+    }
+
+    /// Writes triangles, always normalized, to the autosave file
+    fn autosave(&mut self, ctx: &Context) -> std::io::Result<()> {
+        self.last_autosave = std::time::Instant::now();
+        if self.filename.is_empty() {
+            return Ok(());
+        }
+        let ct = self.output_triangles(ctx, false);
+        write_atomic(&autosave_path(&self.filename), &ct.to_string())
+    }
+
+    /// Starts dragging with the select tool: a vertex of the selection, a triangle or a rubber band
+    fn start_drag(&mut self, pos: na::Point2<f32>, extend: bool) {
+        // Vertices of selected triangles can be grabbed, moving every triangle using them
+        let grabbed = self
+            .mesh
+            .vertices_of(&self.selection)
+            .into_iter()
+            .map(|v| (v, na::distance_squared(&self.mesh.vertices[v], &pos)))
+            .filter(|(_, sqd)| *sqd <= self.snap_radius().powi(2))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        if let Some((v, _)) = grabbed {
+            self.drag = Some(Drag::Vertex(v));
+            return;
+        }
+        // Clicking again on the same spot picks the triangle below the last one,
+        // going back to the top after the bottom one
+        let hits = self
+            .mesh
+            .hits(&pos)
+            .into_iter()
+            .filter(|&(c, _)| self.editable(c))
+            .collect::<Vec<_>>();
+        let depth = match self.pick {
+            Some((p, d)) if !extend && na::distance(&p, &pos) <= PICK_DIST / self.camera.zoom => {
+                (d + 1) % hits.len().max(1)
+            }
+            _ => 0,
+        };
+        self.pick = if hits.is_empty() {
+            None
+        } else {
+            Some((pos, depth))
+        };
+        match hits.get(depth).cloned() {
+            Some(t) => {
+                if depth > 0 {
+                    self.selection = vec![t];
+                    self.notify(format!(
+                        "Picked triangle {} of {} under cursor",
+                        depth + 1,
+                        hits.len()
+                    ));
+                } else if extend {
+                    if let Some(i) = self.selection.iter().position(|s| *s == t) {
+                        self.selection.remove(i);
+                    } else {
+                        self.selection.push(t);
+                    }
+                } else if !self.selection.contains(&t) {
+                    self.selection = vec![t];
+                }
+                self.drag = Some(Drag::Selection(pos, false));
+            }
+            None => {
+                if !extend {
+                    self.selection.clear();
+                }
+                self.drag = Some(Drag::Band(pos));
+            }
+        }
+    }
+
+    /// Copies selected triangles to the clipboard
+    fn copy(&mut self) {
+        self.clipboard = self
+            .selection
+            .iter()
+            .map(|&(c, i)| (c, self.mesh.triangle(c, i).to_vec()))
+            .collect();
+        self.notify(format!("Copied {} triangles", self.clipboard.len()));
+    }
+
+    /// Adds triangles in the clipboard centered on the cursor, selecting them
+    fn paste(&mut self) {
+        if self.clipboard.is_empty() {
+            return;
+        }
+        let n = (self.clipboard.len() * 3) as f32;
+        let center = self
+            .clipboard
+            .iter()
+            .flat_map(|(_, t)| t.iter())
+            .fold(na::Vector2::zeros(), |acc, p| acc + p.coords)
+            / n;
+        let offset = self.m_pos.coords - center;
+        self.selection.clear();
+        for (c, t) in &self.clipboard {
+            let t = t.iter().map(|p| p + offset).collect::<Vec<_>>();
+            self.selection.push((*c, self.mesh.add_triangle(*c, &t)));
+        }
+        self.dirty = true;
+    }
+
+    /// Fills the polygon being drawn with triangles of the current color
+    fn close_polygon(&mut self) {
+        match triangulate(&self.poly) {
+            Some(tris) => {
+                for t in tris {
+                    let tri = [self.poly[t[0]], self.poly[t[1]], self.poly[t[2]]];
+                    self.mesh.add_triangle(self.cur_color, &tri);
+                }
+                self.poly.clear();
+                self.dirty = true;
+            }
+            None => self.notify("Polygon edges are crossing, move them or press Esc"),
+        }
+    }
+
+    /// Triangles of the ellipse centered on the first point being drawn, having corner
+    /// as a corner of its bounding box. If circle, corner is on the rim instead
+    fn ellipse(&self, corner: &na::Point2<f32>, circle: bool) -> Vec<[na::Point2<f32>; 3]> {
+        let center = self.poly[0];
+        let radii = if circle {
+            let r = na::distance(&center, corner);
+            na::Vector2::new(r, r)
+        } else {
+            (corner - center).abs()
+        };
+        if radii.x <= 0.0 || radii.y <= 0.0 {
+            return vec![];
+        }
+        // Approximate perimeter, to keep the rim smooth for every size
+        let rim = std::f32::consts::PI * (radii.x + radii.y);
+        let segments = ((rim / ELLIPSE_SEGMENT) as usize).max(12);
+        ellipse_fan(&center, &radii, segments)
+    }
+
+    /// Adds triangles (on screen) to builder, filled or as wireframes with
+    /// their vertices marked
+    fn build_triangles(
+        &self,
+        builder: &mut graphics::MeshBuilder,
+        tri: &[na::Point2<f32>],
+        color: graphics::Color,
+    ) -> GameResult<()> {
+        let tri = if self.wireframe {
+            let mut lines = stroke(&triangle_edges(tri), LINE_WIDTH);
+            lines.extend(markers(tri, MARKER_SIZE));
+            lines
+        } else {
+            tri.to_vec()
+        };
+        if !tri.is_empty() {
+            builder.triangles(&tri, color)?;
+        }
+        Ok(())
+    }
+
+    /// Saves and quits, staying open if saving failed
+    fn save_and_quit(&mut self, ctx: &mut Context) {
+        match self.save(ctx) {
+            Ok(()) => ggez::event::quit(ctx),
+            Err(e) => self.notify(format!("Unable to save {}: {}", self.filename, e)),
+        }
+    }
+    /// Does what action is bound to, when a key is pressed or a character typed
+    fn run(&mut self, ctx: &mut Context, action: PaintAction) {
+        // Keys zoom the view about the center of the window
+        let (width, height) = graphics::size(ctx);
+        let center = na::Point2::new(width / 2.0, height / 2.0);
+        match action {
+            PaintAction::Help => self.show_help = !self.show_help,
+            PaintAction::Cancel => {
+                // Close help, if a shape is being drawn remove it, else drop the selection
+                if self.show_help {
+                    self.show_help = false;
+                } else if !self.poly.is_empty() {
+                    self.poly.clear();
+                } else if !self.selection.is_empty() {
+                    self.selection.clear();
                 } else {
-                    builder.triangles(&poly, self.ct.colors[self.cur_color])?;
+                    self.save_and_quit(ctx);
+                }
+            }
+            PaintAction::Save => {
+                if let Err(e) = self.save(ctx) {
+                    self.notify(format!("Unable to save {}: {}", self.filename, e));
+                }
+            }
+            PaintAction::Copy => self.copy(),
+            PaintAction::Paste => self.paste(),
+            PaintAction::NextTool => {
+                self.tool = match self.tool {
+                    Tool::Triangle => Tool::Polygon,
+                    Tool::Polygon => Tool::Ellipse,
+                    Tool::Ellipse => Tool::Select,
+                    Tool::Select => Tool::Triangle,
+                };
+                self.poly.clear();
+                self.drag = None;
+            }
+            PaintAction::ClosePolygon => {
+                if self.tool == Tool::Polygon {
+                    self.close_polygon();
                 }
             }
-            2 => {
+            PaintAction::RemovePoint => {
+                // Remove the last point of the shape being drawn
+                self.poly.pop();
+            }
+            PaintAction::Recolor if !self.editable(self.cur_color) => {
+                self.notify("Current color is hidden or locked");
+            }
+            PaintAction::Recolor => {
+                // Move selection on top of current color
+                self.selection = self.mesh.recolor(&self.selection, self.cur_color);
+                self.dirty = true;
+            }
+            PaintAction::RemoveColor => self.remove_layer(),
+            PaintAction::DeleteSelection => {
+                self.mesh.remove_triangles(&self.selection);
+                self.selection.clear();
+                self.forget_indices();
+                self.dirty = true;
+            }
+            PaintAction::NextColor => {
+                self.cur_color = (self.cur_color + 1) % self.mesh.colors.len();
+            }
+            PaintAction::PreviousColor => {
+                let n = self.mesh.colors.len();
+                self.cur_color = (self.cur_color + n - 1) % n;
+            }
+            PaintAction::AddColor => self.color_prompt = Some("#".to_owned()),
+            PaintAction::ToggleSnap => {
+                self.use_snap = !self.use_snap;
+            }
+            PaintAction::ToggleWireframe => {
+                self.wireframe = !self.wireframe;
+            }
+            PaintAction::DeleteUnderCursor => {
+                // Get triangle under cursor and delete it
+                let hit = self
+                    .mesh
+                    .hits(&self.m_pos)
+                    .into_iter()
+                    .find(|&(c, _)| self.editable(c));
+                if let Some((c, i)) = hit {
+                    self.notify(format!("Deleting triangle {} {}", c, i));
+                    self.mesh.remove_triangles(&[(c, i)]);
+                    // Indices after the removed one have changed
+                    self.selection.clear();
+                    self.forget_indices();
+                    self.dirty = true;
+                }
+            }
+            PaintAction::GridGrow => {
+                self.grid *= 2.0;
+            }
+            PaintAction::GridShrink => {
+                self.grid = (self.grid / 2.0).max(MIN_GRID);
+            }
+            PaintAction::SnapGrow => {
+                self.snap_dist += 1.0;
+            }
+            PaintAction::SnapShrink => {
+                self.snap_dist = (self.snap_dist - 1.0).max(0.0);
+            }
+            PaintAction::ToggleGrid => {
+                self.use_grid = !self.use_grid;
+            }
+            PaintAction::ToggleEdgeSnap => {
+                self.snap_edges = !self.snap_edges;
+            }
+            PaintAction::TogglePalette => {
+                self.show_palette = !self.show_palette;
+            }
+            PaintAction::MoveColorUp => self.move_layer(true),
+            PaintAction::MoveColorDown => self.move_layer(false),
+            PaintAction::LessAlpha => self.change_alpha(-0.1),
+            PaintAction::MoreAlpha => self.change_alpha(0.1),
+            PaintAction::HideColor => {
+                let c = self.cur_color;
+                self.layers[c].hidden = !self.layers[c].hidden;
+                self.deselect_color(c);
+            }
+            PaintAction::LockColor => {
+                let c = self.cur_color;
+                self.layers[c].locked = !self.layers[c].locked;
+                self.deselect_color(c);
+            }
+            PaintAction::ToggleReference => {
+                self.show_background = !self.show_background;
+            }
+            PaintAction::ReferenceFainter => {
+                self.background_opacity = (self.background_opacity - 0.1).max(0.0);
+            }
+            PaintAction::ReferenceStronger => {
+                self.background_opacity = (self.background_opacity + 0.1).min(1.0);
+            }
+            // Transform the scene itself rather than the view
+            PaintAction::MoveLeft => { self.mesh.translate(-self.snap_radius(), 0.0); self.dirty = true; }
+            PaintAction::MoveDown => { self.mesh.translate(0.0, self.snap_radius()); self.dirty = true; }
+            PaintAction::MoveUp => { self.mesh.translate(0.0, -self.snap_radius()); self.dirty = true; }
+            PaintAction::MoveRight => { self.mesh.translate(self.snap_radius(), 0.0); self.dirty = true; }
+            PaintAction::ScaleUp => { self.mesh.scale(ZOOM_STEP, ZOOM_STEP); self.dirty = true; }
+            PaintAction::ScaleDown => { self.mesh.scale(1.0 / ZOOM_STEP, 1.0 / ZOOM_STEP); self.dirty = true; }
+            PaintAction::PanLeft => self.camera.offset.x -= self.snap_dist,
+            PaintAction::PanDown => self.camera.offset.y += self.snap_dist,
+            PaintAction::PanUp => self.camera.offset.y -= self.snap_dist,
+            PaintAction::PanRight => self.camera.offset.x += self.snap_dist,
+            PaintAction::ZoomIn => self.camera.zoom_at(&center, ZOOM_STEP),
+            PaintAction::ZoomOut => self.camera.zoom_at(&center, 1.0 / ZOOM_STEP),
+            PaintAction::ResetView => self.camera = Camera::new(self.margin),
+        }
+    }
+}
+
+impl EventHandler for Paint {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        // Periodically write unsaved edits aside, to recover them after a crash
+        if self.dirty && self.last_autosave.elapsed().as_secs() >= AUTOSAVE_SECS {
+            if let Err(e) = self.autosave(ctx) {
+                self.notify(format!("Unable to autosave: {}", e));
+            }
+        }
+        Ok(())
+    }
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        graphics::clear(ctx, graphics::BLACK);
+        if let (Some(img), true) = (&self.background, self.show_background) {
+            // Stretch image over the drawing area, where triangles are normalized
+            let (width, height) = graphics::size(ctx);
+            let scale = na::Vector2::new(
+                (width - 2.0 * self.margin) / img.width() as f32,
+                (height - 2.0 * self.margin) / img.height() as f32,
+            ) * self.camera.zoom;
+            graphics::draw(
+                ctx,
+                img,
+                graphics::DrawParam::default()
+                    .dest(self.camera.screen_of(&na::Point2::origin()))
+                    .scale(scale)
+                    .color(graphics::Color::new(1.0, 1.0, 1.0, self.background_opacity)),
+            )?;
+        }
+
+        let mut builder = graphics::MeshBuilder::new();
+        if self.use_grid {
+            self.build_grid(ctx, &mut builder);
+        }
+        for i in 0..self.mesh.colors.len() {
+            if !self.mesh.triangles[i].is_empty() && !self.layers[i].hidden {
+                // Map triangle points to screen
+                let tri = self.mshift(&self.mesh.points(i));
+                self.build_triangles(&mut builder, &tri, self.mesh.colors[i])?;
+            }
+        }
+        // Where a click (or drag) would put a point, marked if snapped
+        let shift = ggez::input::keyboard::active_mods(ctx).contains(KeyMods::SHIFT);
+        let (target, snapped) = match (self.tool, &self.drag) {
+            (Tool::Select, Some(Drag::Vertex(v))) => self.snap(self.m_pos, Some(*v), false),
+            (Tool::Select, _) => (self.m_pos, None),
+            (Tool::Ellipse, _) => self.snap(self.m_pos, None, false),
+            _ => self.snap(self.m_pos, None, shift),
+        };
+        let color = self.mesh.colors[self.cur_color];
+        match (self.tool, self.poly.len()) {
+            (Tool::Triangle, 1) => {
+                // Just a line, until the second point is there
+                let line = self.mshift(&[self.poly[0], target]);
+                let line = stroke(&polyline_edges(&line, false), LINE_WIDTH);
+                if !line.is_empty() {
+                    builder.triangles(&line, color)?;
+                }
+            }
+            (Tool::Triangle, 2) => {
                 let mut poly = self.poly.clone();
-                poly.push(self.m_pos);
-                // Offset poly by margin
-                let poly = self.mshift(&poly);
-                if self.wireframe {
-                    builder.polyline(
-                        graphics::DrawMode::stroke(1.0),
-                        &poly,
-                        self.ct.colors[self.cur_color],
-                    )?;
-                } else {
-                    builder.triangles(&poly, self.ct.colors[self.cur_color])?;
+                poly.push(target);
+                self.build_triangles(&mut builder, &self.mshift(&poly), color)?;
+            }
+            (Tool::Polygon, n) if n > 0 => {
+                let mut poly = self.poly.clone();
+                poly.push(target);
+                // Fill the polygon as it would be closed now, if possible
+                if let Some(tris) = triangulate(&poly) {
+                    let tri = tris
+                        .iter()
+                        .flat_map(|t| t.iter().map(|&k| poly[k]))
+                        .collect::<Vec<_>>();
+                    self.build_triangles(&mut builder, &self.mshift(&tri), color)?;
+                }
+                let line = stroke(&polyline_edges(&self.mshift(&poly), false), LINE_WIDTH);
+                if !line.is_empty() {
+                    builder.triangles(&line, graphics::WHITE)?;
                 }
             }
+            (Tool::Ellipse, 1) => {
+                let tri = self
+                    .ellipse(&target, shift)
+                    .iter()
+                    .flat_map(|t| t.iter().cloned())
+                    .collect::<Vec<_>>();
+                self.build_triangles(&mut builder, &self.mshift(&tri), color)?;
+            }
             _ => {}
         }
 
-        // Draw a triangle as a pointer, over other objects for better viewing
+        // Outline selected triangles and the rubber band
+        for &(c, i) in &self.selection {
+            let tri = self.mshift(&self.mesh.triangle(c, i));
+            builder.polygon(graphics::DrawMode::stroke(2.0), &tri, graphics::WHITE)?;
+        }
+        if let Some(Drag::Band(start)) = &self.drag {
+            let (a, b) = (
+                self.camera.screen_of(start),
+                self.camera.screen_of(&self.m_pos),
+            );
+            builder.rectangle(
+                graphics::DrawMode::stroke(1.0),
+                graphics::Rect::new(
+                    a.x.min(b.x),
+                    a.y.min(b.y),
+                    (a.x - b.x).abs(),
+                    (a.y - b.y).abs(),
+                ),
+                graphics::WHITE,
+            );
+        }
+
+        if let Some(snap) = snapped {
+            let p = self.camera.screen_of(&target);
+            builder.circle(graphics::DrawMode::stroke(1.5), p, 5.0, 0.5, snap.color());
+        }
+
+        // Draw a triangle as a pointer, over other objects for better viewing,
+        // sized on screen as snap distance is
+        let m_pos = self.camera.screen_of(&self.m_pos);
         let mut pointer = vec![
-            self.m_pos,
-            na::Point2::new(self.m_pos.x + self.snap_dist, self.m_pos.y),
-            na::Point2::new(self.m_pos.x, self.m_pos.y + self.snap_dist),
+            m_pos,
+            na::Point2::new(m_pos.x + self.snap_dist, m_pos.y),
+            na::Point2::new(m_pos.x, m_pos.y + self.snap_dist),
         ];
         if self.use_snap {
             pointer.append(&mut vec![
-                self.m_pos,
-                na::Point2::new(self.m_pos.x - self.snap_dist, self.m_pos.y),
-                na::Point2::new(self.m_pos.x, self.m_pos.y - self.snap_dist),
+                m_pos,
+                na::Point2::new(m_pos.x - self.snap_dist, m_pos.y),
+                na::Point2::new(m_pos.x, m_pos.y - self.snap_dist),
             ]);
         }
-        // Offset by margin
-        let pointer = self.mshift(&pointer);
 
-        builder.triangles(&pointer, self.ct.colors[self.cur_color])?;
+        builder.triangles(&pointer, self.mesh.colors[self.cur_color])?;
 
         // At least the cursor is being drawn
         let mesh = builder.build(ctx)?;
         graphics::draw(ctx, &mesh, graphics::DrawParam::default())?;
 
-        graphics::present(ctx)
+        if self.show_palette {
+            self.draw_panel(ctx)?;
+        }
+        self.draw_hud(ctx)?;
+        if self.show_help {
+            self.draw_help(ctx)?;
+        }
+
+        if self.quit_prompt {
+            let (width, height) = graphics::size(ctx);
+            let text = graphics::Text::new(
+                "Unsaved changes!\n[Y] save and quit\n[N] quit without saving\n[Esc] keep painting",
+            );
+            let (w, h) = text.dimensions(ctx);
+            graphics::draw(
+                ctx,
+                &text,
+                (na::Point2::new(
+                    (width - w as f32) * 0.5,
+                    (height - h as f32) * 0.5,
+                ),),
+            )?;
+        }
+        Ok(())
     }
     fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
-        // Correct cursor position using margin
-        self.m_pos = na::Point2::new(x - self.margin, y - self.margin);
+        let screen = na::Point2::new(x, y);
+        if let Some(Drag::Pan(last)) = self.drag.as_mut() {
+            self.camera.offset += screen - *last;
+            *last = screen;
+        }
+        // Correct cursor position using camera
+        self.m_pos = self.camera.scene_of(&screen);
+        match self.drag.as_mut() {
+            Some(Drag::Selection(last, detached)) => {
+                // Selected triangles move on their own, not stretching the others
+                if !*detached {
+                    self.mesh.detach(&self.selection);
+                    *detached = true;
+                }
+                let offset = self.m_pos - *last;
+                *last = self.m_pos;
+                // Moving away from the picked spot, start picking from the top
+                if let Some((p, _)) = self.pick {
+                    if na::distance(&p, &self.m_pos) > PICK_DIST / self.camera.zoom {
+                        self.pick = None;
+                    }
+                }
+                let vs = self.mesh.vertices_of(&self.selection);
+                self.mesh.translate_vertices(&vs, &offset);
+                self.dirty = true;
+            }
+            Some(Drag::Vertex(v)) => {
+                // Snap to anything but the vertex being dragged
+                let v = *v;
+                let (pos, _) = self.snap(self.m_pos, Some(v), false);
+                self.mesh.move_vertices(&[(v, pos)]);
+                self.dirty = true;
+            }
+            _ => {}
+        }
     }
-    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if self.quit_prompt {
+            return;
+        }
+        // Clicking on a layer in the palette selects it, or toggles its flags
+        if let Some((c, col)) = self.panel_at(x, y) {
+            if button == MouseButton::Left {
+                match col {
+                    1 => {
+                        self.layers[c].hidden = !self.layers[c].hidden;
+                        self.deselect_color(c);
+                    }
+                    2 => {
+                        self.layers[c].locked = !self.layers[c].locked;
+                        self.deselect_color(c);
+                    }
+                    _ => self.cur_color = c,
+                }
+            }
+            return;
+        }
+        if button == MouseButton::Middle {
+            self.drag = Some(Drag::Pan(na::Point2::new(x, y)));
+            return;
+        }
+        let pos = self.camera.scene_of(&na::Point2::new(x, y));
+        if self.tool == Tool::Select {
+            if button == MouseButton::Left {
+                let extend = ggez::input::keyboard::active_mods(ctx).contains(KeyMods::SHIFT);
+                self.start_drag(pos, extend);
+            }
+            return;
+        }
         let add = match button {
             MouseButton::Left => true,
             _ => false,
         };
-        // Get mutable access to x and y, with margins removed, for snapping
-        let mut x = x - self.margin;
-        let mut y = y - self.margin;
-        if self.use_snap {
-            // Snap mouse coords to existing points
-            let nearest = self.ct.nearest(1, &na::Point2::new(x, y));
-            if !nearest.is_empty() {
-                let (col_i, pt_i, sqd) = nearest[0];
-                if sqd <= self.snap_dist * self.snap_dist {
-                    let p = self.ct.triangles[col_i][pt_i];
-                    x = p.x;
-                    y = p.y;
+        if add && !self.editable(self.cur_color) {
+            self.notify("Current color is hidden or locked");
+            return;
+        }
+        // Snap mouse coords to existing points, edges or grid, or constrain
+        // lines being drawn with Shift
+        let shift = ggez::input::keyboard::active_mods(ctx).contains(KeyMods::SHIFT);
+        let constrain = shift && (self.tool == Tool::Triangle || self.tool == Tool::Polygon);
+        let (p, _) = self.snap(pos, None, constrain);
+        match self.tool {
+            Tool::Triangle => match self.poly.len() {
+                0 | 1 if add => {
+                    self.poly.push(p);
+                }
+                2 if add => {
+                    self.poly.push(p);
+                    self.mesh.add_triangle(self.cur_color, &self.poly);
+                    self.poly.clear();
+                    self.dirty = true;
+                }
+                _ => {
+                    // Do nothing, this case shouldn't even happen
+                }
+            },
+            Tool::Polygon if add => {
+                // Clicking the first point again closes the polygon
+                if self.poly.len() >= 3 && na::distance(&self.poly[0], &p) <= self.snap_radius() {
+                    self.close_polygon();
+                } else {
+                    self.poly.push(p);
                 }
             }
+            Tool::Ellipse if add => {
+                if self.poly.is_empty() {
+                    self.poly.push(p);
+                } else {
+                    let circle = ggez::input::keyboard::active_mods(ctx).contains(KeyMods::SHIFT);
+                    for tri in self.ellipse(&p, circle) {
+                        self.mesh.add_triangle(self.cur_color, &tri);
+                    }
+                    self.poly.clear();
+                    self.dirty = true;
+                }
+            }
+            _ => {}
         }
-        match self.poly.len() {
-            0 | 1 if add => {
-                self.poly.push(na::Point2::new(x, y));
+    }
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) {
+        if button == MouseButton::Middle {
+            if let Some(Drag::Pan(_)) = self.drag {
+                self.drag = None;
             }
-            2 if add => {
-                self.poly.push(na::Point2::new(x, y));
-                self.ct.triangles[self.cur_color].append(&mut self.poly);
+            return;
+        }
+        if button != MouseButton::Left {
+            return;
+        }
+        match self.drag.take() {
+            // Select triangles in the rubber band
+            Some(Drag::Band(start)) => {
+                for t in self.mesh.inside(&start, &self.m_pos) {
+                    if self.editable(t.0) && !self.selection.contains(&t) {
+                        self.selection.push(t);
+                    }
+                }
             }
-            _ => {
-                // Do nothing, this case shouldn't even happen
+            // A vertex dropped over another one (i.e. snapped) becomes the same vertex
+            Some(Drag::Vertex(v)) => {
+                let p = self.mesh.vertices[v];
+                if let Some(into) =
+                    (0..self.mesh.vertices.len()).find(|&i| i != v && self.mesh.vertices[i] == p)
+                {
+                    if self.mesh.weld(v, into) {
+                        // Triangles collapsed, indices have changed
+                        self.selection.clear();
+                    }
+                }
             }
+            _ => {}
         }
     }
     fn key_down_event(
         &mut self,
         ctx: &mut Context,
         keycode: KeyCode,
-        _keymods: KeyMods,
+        keymods: KeyMods,
         _repeat: bool,
     ) {
-        match keycode {
-            KeyCode::Escape => {
-                // If a triangle is being created, remove it
-                if !self.poly.is_empty() {
-                    self.poly.clear();
-                } else {
-                    println!(
-                        "Saving list of triangles by color over file {}, absolute coords: {}",
-                        self.filename, self.write_absolute
-                    );
-                    if !self.write_absolute {
-                        let (width, height) = graphics::size(ctx);
-                        let width = width - self.margin * 2.0;
-                        let height = height - self.margin * 2.0;
-                        self.ct.scale(1.0 / width, 1.0 / height);
-                    }
-                    let mut f = std::fs::File::create(&self.filename).expect("Cannot create file");
-                    for i in 0..self.ct.colors.len() {
-                        writeln!(
-                            &mut f,
-                            "{} {}",
-                            self.ct.colors[i].to_rgb_u32(),
-                            self.ct.triangles[i]
-                                .iter()
-                                .map(|p| format!("{},{}", p.x, p.y))
-                                .collect::<Vec<String>>()
-                                .join(" ")
-                        )
-                        .expect("Unable to write to file");
-                    }
+        if self.quit_prompt {
+            match keycode {
+                KeyCode::Y => self.save_and_quit(ctx),
+                KeyCode::N => {
+                    // Edits are discarded on purpose, don't recover them
+                    let _ = std::fs::remove_file(autosave_path(&self.filename));
                     ggez::event::quit(ctx);
                 }
+                KeyCode::Escape => self.quit_prompt = false,
+                _ => {}
             }
-            KeyCode::N => {
-                let nc = (self.cur_color + 1) % self.ct.colors.len();
-                self.cur_color = nc;
-                println!("Current color {:?}", self.ct.colors[nc]);
-            }
-            KeyCode::S => {
-                self.use_snap = !self.use_snap;
-                println!(
-                    "Toggling snap. Snap is now {}",
-                    if self.use_snap { "on" } else { "off" }
-                );
-            }
-            KeyCode::W => {
-                self.wireframe = !self.wireframe;
-            }
-            KeyCode::X => {
-                // Get triangle under cursor and delete it
-                if let Some((c, i)) = self.ct.colliding(&self.m_pos) {
-                    println!("Deleting triangle {} {}", c, i);
-                    self.ct.remove_triangle(c, i);
+            return;
+        }
+        if let Some(text) = self.color_prompt.as_mut() {
+            match keycode {
+                KeyCode::Return => match str2col(text) {
+                    Ok(color) => {
+                        self.color_prompt = None;
+                        self.cur_color = self.add_color(color);
+                    }
+                    Err(e) => {
+                        let msg = format!("Invalid color {}: {}", text, e);
+                        self.notify(msg);
+                    }
+                },
+                KeyCode::Back => {
+                    text.pop();
                 }
+                KeyCode::Escape => self.color_prompt = None,
+                _ => {}
             }
-            KeyCode::Equals => {
-                self.snap_dist += 1.0;
-            }
-            KeyCode::Subtract => {
-                self.snap_dist -= 1.0;
-            }
-            KeyCode::H => { self.ct.translate(-self.snap_dist, 0.0); }
-            KeyCode::J => { self.ct.translate(0.0, self.snap_dist); }
-            KeyCode::K => { self.ct.translate(0.0, -self.snap_dist); }
-            KeyCode::L => { self.ct.translate(self.snap_dist, 0.0); }
-            KeyCode::I => { self.ct.scale(1.25, 1.25); }
-            KeyCode::O => { self.ct.scale(1.0 / 1.25, 1.0 / 1.25); }
-            k => {
-                println!("Key code pressed {:?}", k);
+            return;
+        }
+        // Unbound keys are ignored silently, as keys typing # or ? end up here
+        if let Some(action) = self.keys.action(keycode, keymods) {
+            self.run(ctx, action);
+        }
+    }
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) {
+        // Zoom about the cursor, so what is under it stays there
+        let cursor = self.camera.screen_of(&self.m_pos);
+        self.camera.zoom_at(&cursor, ZOOM_STEP.powf(y));
+    }
+    fn text_input_event(&mut self, ctx: &mut Context, ch: char) {
+        // Typing # starts writing a new color, usually in hex, but names work too
+        match self.color_prompt.as_mut() {
+            Some(color) if !ch.is_control() => color.push(ch),
+            None if !self.quit_prompt => {
+                // Some actions are bound to characters, whatever the keyboard layout
+                if let Some(action) = self.keys.char_action(ch) {
+                    self.run(ctx, action);
+                }
             }
+            _ => {}
         }
     }
+    /// Called when window is closed, asks for confirmation if there are unsaved edits
+    fn quit_event(&mut self, ctx: &mut Context) -> bool {
+        if !self.dirty || self.quit_prompt {
+            // Nothing to lose, or closing again while asking: quit, keeping
+            // the autosave around to recover the edits on next start
+            return false;
+        }
+        if let Err(e) = self.autosave(ctx) {
+            self.notify(format!("Unable to autosave: {}", e));
+        }
+        self.quit_prompt = true;
+        true // Don't quit yet
+    }
 }
 
 impl State for Paint {
-    fn next_state(&mut self) -> Option<States> {
+    fn next_state(&mut self) -> Option<StateChange> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{change_stack, wants_token, Enemy, Hook, Payload, StateChange, States};
+    use crate::wording::ESCAPE;
+
+    fn enemy(word: &str) -> Enemy {
+        Enemy {
+            pos_x: 0.0,
+            pos_y: 0.0,
+            speed: 1.0,
+            word: word.to_owned(),
+            font_size: 30.0,
+        }
+    }
+
+    #[test]
+    fn tokens_are_wanted_when_next() {
+        let enemies = vec![enemy("i⎋"), enemy("⎋q")];
+        assert!(wants_token(&enemies, None, ESCAPE));
+        assert!(!wants_token(&enemies[..1], None, ESCAPE));
+        // Only the target counts once there is one
+        assert!(!wants_token(&enemies, Some(&enemy("x⎋")), ESCAPE));
+        assert!(wants_token(&enemies[..1], Some(&enemy("⎋")), ESCAPE));
+        assert!(!wants_token(&[], None, ESCAPE));
+    }
+
+    #[test]
+    fn states_are_stacked() {
+        let mut stack = vec![];
+        assert_eq!(
+            change_stack(&mut stack, States::StartScreen.into()),
+            vec![(States::StartScreen, Hook::Enter(Payload::None))]
+        );
+
+        // Pushing covers the running state, popping resumes it
+        let push = StateChange::Push(States::Pause, Payload::None);
+        assert_eq!(
+            change_stack(&mut stack, push),
+            vec![
+                (States::StartScreen, Hook::Cover),
+                (States::Pause, Hook::Enter(Payload::None)),
+            ]
+        );
+        assert_eq!(stack, vec![States::StartScreen, States::Pause]);
+        assert_eq!(
+            change_stack(&mut stack, StateChange::Pop(Payload::Retry)),
+            vec![
+                (States::Pause, Hook::Exit),
+                (States::StartScreen, Hook::Resume(Payload::Retry)),
+            ]
+        );
+        assert_eq!(stack, vec![States::StartScreen]);
+
+        // The bottom state can't be popped
+        assert_eq!(
+            change_stack(&mut stack, StateChange::Pop(Payload::None)),
+            vec![]
+        );
+        assert_eq!(stack, vec![States::StartScreen]);
+
+        // Switching leaves every state, top first
+        change_stack(
+            &mut stack,
+            StateChange::Push(States::Settings, Payload::None),
+        );
+        let switch = StateChange::Switch(States::Game, Payload::Level(2));
+        assert_eq!(
+            change_stack(&mut stack, switch),
+            vec![
+                (States::Settings, Hook::Exit),
+                (States::StartScreen, Hook::Exit),
+                (States::Game, Hook::Enter(Payload::Level(2))),
+            ]
+        );
+        assert_eq!(stack, vec![States::Game]);
+    }
+}
@@ -0,0 +1,592 @@
+//! Menus of the UI, declared as a list of entries and navigated with the keyboard:
+//! Up and Down select an entry, Enter activates it, Left and Right change
+//! toggles and sliders, and Esc gets back from a submenu.
+
+use crate::StateChange;
+use ggez::input::keyboard::KeyCode;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A value changed by a menu, read by the states sharing it
+pub type Shared<T> = Rc<RefCell<T>>;
+
+pub fn shared<T>(value: T) -> Shared<T> {
+    Rc::new(RefCell::new(value))
+}
+
+/// A number changed by steps within a range
+#[derive(Clone, Debug)]
+pub struct Slider {
+    pub value: Shared<f32>,
+    pub min: f32,
+    pub max: f32,
+    pub step: f32,
+}
+
+impl Slider {
+    pub fn new(value: Shared<f32>, min: f32, max: f32, step: f32) -> Slider {
+        Slider {
+            value,
+            min,
+            max,
+            step,
+        }
+    }
+
+    /// Moves the value by some steps, keeping it in range
+    fn change(&self, steps: f32) {
+        let mut value = self.value.borrow_mut();
+        *value = (*value + steps * self.step).clamp(self.min, self.max);
+    }
+
+    /// The value, with as many decimals as the step needs
+    fn text(&self) -> String {
+        let decimals = if self.step >= 1.0 {
+            0
+        } else {
+            (-self.step.log10()).ceil() as usize
+        };
+        format!("{:.*}", decimals, *self.value.borrow())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum MenuEntry {
+    /// A message that displays a message and transitions to another state when key is pressed
+    Transition(f32, String, KeyCode, StateChange),
+    /// Just a message text, no transition
+    Message(f32, String),
+    /// Lines of the report received entering the UI, if any
+    Details(f32),
+    /// Some vertical space between entries
+    VSpace(f32),
+    /// A message that transitions to another state when selected and activated
+    Item(f32, String, StateChange),
+    /// A label with a switch, flipped when activated or with Left and Right
+    Toggle(f32, String, Shared<bool>),
+    /// A label with a number, changed with Left and Right
+    Slider(f32, String, Slider),
+    /// A label with one of some values, by its index, changed with Left and
+    /// Right or when activated
+    Choice(f32, String, Vec<String>, Shared<usize>),
+    /// A label with some text, typed while selected
+    Input(f32, String, Shared<String>),
+    /// Entries of which only some rows are shown, scrolling to the selected one
+    List(usize, Vec<MenuEntry>),
+    /// A message that opens other entries in place of these when activated
+    Submenu(f32, String, Vec<MenuEntry>),
+    /// A message that gets back from the submenu when activated
+    Back(f32, String),
+}
+
+impl MenuEntry {
+    /// True if the entry can be selected
+    fn selectable(&self) -> bool {
+        match self {
+            MenuEntry::Message(..) | MenuEntry::Details(_) | MenuEntry::VSpace(_) => false,
+            MenuEntry::List(_, items) => !items.is_empty(),
+            _ => true,
+        }
+    }
+
+    /// Scale and text of the line showing the entry
+    fn line(&self, selected: bool) -> (f32, String) {
+        match self {
+            MenuEntry::Transition(sc, msg, _, _)
+            | MenuEntry::Message(sc, msg)
+            | MenuEntry::Item(sc, msg, _)
+            | MenuEntry::Submenu(sc, msg, _)
+            | MenuEntry::Back(sc, msg) => (*sc, msg.clone()),
+            MenuEntry::Toggle(sc, label, on) => {
+                let on = if *on.borrow() { "on" } else { "off" };
+                (*sc, format!("{}: {}", label, on))
+            }
+            MenuEntry::Slider(sc, label, slider) if selected => {
+                (*sc, format!("{}: < {} >", label, slider.text()))
+            }
+            MenuEntry::Slider(sc, label, slider) => (*sc, format!("{}: {}", label, slider.text())),
+            MenuEntry::Choice(sc, label, values, i) => {
+                let value = values.get(*i.borrow()).map_or("", String::as_str);
+                if selected {
+                    (*sc, format!("{}: < {} >", label, value))
+                } else {
+                    (*sc, format!("{}: {}", label, value))
+                }
+            }
+            MenuEntry::Input(sc, label, text) => {
+                let cursor = if selected { "_" } else { "" };
+                (*sc, format!("{}: {}{}", label, text.borrow(), cursor))
+            }
+            MenuEntry::Details(_) | MenuEntry::VSpace(_) | MenuEntry::List(..) => {
+                (0.0, String::new())
+            }
+        }
+    }
+
+    /// The change of state of a Transition bound to key, here or in a list
+    fn hotkey(&self, key: KeyCode) -> Option<StateChange> {
+        match self {
+            MenuEntry::Transition(_, _, k, change) if *k == key => Some(change.clone()),
+            MenuEntry::List(_, items) => items.iter().find_map(|e| e.hotkey(key)),
+            _ => None,
+        }
+    }
+}
+
+/// A line of a menu to draw
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    Text {
+        scale: f32,
+        text: String,
+        selected: bool,
+    },
+    Space(f32),
+}
+
+/// Entries shown at once, with the one selected
+struct Page {
+    entries: Vec<MenuEntry>,
+    selected: Option<usize>,
+    // Row selected, when the entry selected is a list
+    row: usize,
+}
+
+impl Page {
+    fn new(entries: Vec<MenuEntry>) -> Page {
+        let selected = entries.iter().position(MenuEntry::selectable);
+        Page {
+            entries,
+            selected,
+            row: 0,
+        }
+    }
+
+    /// The entry selected, or the row selected in a list
+    fn current(&self) -> Option<&MenuEntry> {
+        match self.entries.get(self.selected?)? {
+            MenuEntry::List(_, items) => items.get(self.row),
+            entry => Some(entry),
+        }
+    }
+
+    /// Selects the next entry, or the next row of a list, wrapping around
+    fn down(&mut self) {
+        let sel = match self.selected {
+            Some(sel) => sel,
+            None => {
+                self.selected = self.entries.iter().position(MenuEntry::selectable);
+                self.row = 0;
+                return;
+            }
+        };
+        if let MenuEntry::List(_, items) = &self.entries[sel] {
+            if self.row + 1 < items.len() {
+                self.row += 1;
+                return;
+            }
+        }
+        let n = self.entries.len();
+        self.selected = (1..=n)
+            .map(|i| (sel + i) % n)
+            .find(|&i| self.entries[i].selectable());
+        self.row = 0;
+    }
+
+    /// Selects the previous entry, or the previous row of a list, wrapping around
+    fn up(&mut self) {
+        let n = self.entries.len();
+        match self.selected {
+            Some(sel) => {
+                if let MenuEntry::List(..) = &self.entries[sel] {
+                    if self.row > 0 {
+                        self.row -= 1;
+                        return;
+                    }
+                }
+                self.selected = (1..=n)
+                    .map(|i| (sel + n - i) % n)
+                    .find(|&i| self.entries[i].selectable());
+            }
+            None => self.selected = self.entries.iter().rposition(MenuEntry::selectable),
+        }
+        self.row = match self.selected.map(|i| &self.entries[i]) {
+            Some(MenuEntry::List(_, items)) => items.len() - 1,
+            _ => 0,
+        };
+    }
+}
+
+/// Entries of a UI, with the submenus opened and the report to show in details
+pub struct Menu {
+    page: Page,
+    // Pages the submenus were opened from, the last one is the parent
+    parents: Vec<Page>,
+    details: Vec<String>,
+}
+
+impl Menu {
+    pub fn new(entries: Vec<MenuEntry>) -> Menu {
+        Menu {
+            page: Page::new(entries),
+            parents: Vec::new(),
+            details: Vec::new(),
+        }
+    }
+
+    /// Closes the submenus and selects the first entry
+    pub fn reset(&mut self) {
+        while self.close() {}
+        self.page.selected = self.page.entries.iter().position(MenuEntry::selectable);
+        self.page.row = 0;
+    }
+
+    /// Sets the lines shown by Details entries
+    pub fn set_details(&mut self, details: Vec<String>) {
+        self.details = details;
+    }
+
+    /// Handles a key pressed, returning the change of state it asks for.
+    /// Enter activates the entry selected, if any, before hot-keys. While a
+    /// text is being typed, hot-keys don't work and Esc leaves the text,
+    /// closing the submenu or selecting nothing
+    pub fn key(&mut self, key: KeyCode) -> Option<StateChange> {
+        let typing = matches!(self.page.current(), Some(MenuEntry::Input(..)));
+        if typing && key == KeyCode::Escape {
+            if !self.close() {
+                self.page.selected = None;
+            }
+            return None;
+        }
+        let enter = key == KeyCode::Return || key == KeyCode::NumpadEnter;
+        if enter && self.page.selected.is_some() {
+            return self.activate();
+        }
+        if !typing {
+            if let Some(change) = self.page.entries.iter().find_map(|e| e.hotkey(key)) {
+                return Some(change);
+            }
+        }
+        match key {
+            KeyCode::Up => self.page.up(),
+            KeyCode::Down | KeyCode::Tab => self.page.down(),
+            KeyCode::Left => self.change(-1.0),
+            KeyCode::Right => self.change(1.0),
+            KeyCode::Return | KeyCode::NumpadEnter => return self.activate(),
+            KeyCode::Back => {
+                if let Some(MenuEntry::Input(_, _, text)) = self.page.current() {
+                    text.borrow_mut().pop();
+                }
+            }
+            KeyCode::Escape => {
+                self.close();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Handles a character typed, adding it to the text selected if any
+    pub fn text(&mut self, ch: char) {
+        if let Some(MenuEntry::Input(_, _, text)) = self.page.current() {
+            if !ch.is_control() {
+                text.borrow_mut().push(ch);
+            }
+        }
+    }
+
+    /// Lines to draw, top to bottom
+    pub fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for (i, entry) in self.page.entries.iter().enumerate() {
+            let selected = self.page.selected == Some(i);
+            match entry {
+                MenuEntry::VSpace(y) => lines.push(Line::Space(*y)),
+                MenuEntry::Details(sc) => {
+                    lines.extend(self.details.iter().map(|text| Line::Text {
+                        scale: *sc,
+                        text: text.clone(),
+                        selected: false,
+                    }))
+                }
+                MenuEntry::List(rows, items) => {
+                    // Scroll just enough to show the row selected
+                    let row = if selected { self.page.row } else { 0 };
+                    let top = (row + 1).saturating_sub((*rows).max(1));
+                    for (j, item) in items.iter().enumerate().skip(top).take(*rows) {
+                        let selected = selected && j == row;
+                        let (scale, text) = item.line(selected);
+                        lines.push(Line::Text {
+                            scale,
+                            text,
+                            selected,
+                        });
+                    }
+                }
+                entry => {
+                    let (scale, text) = entry.line(selected);
+                    lines.push(Line::Text {
+                        scale,
+                        text,
+                        selected,
+                    });
+                }
+            }
+        }
+        lines
+    }
+
+    /// Activates the entry selected, returning the change of state it asks for
+    fn activate(&mut self) -> Option<StateChange> {
+        match self.page.current()? {
+            MenuEntry::Transition(_, _, _, change) | MenuEntry::Item(_, _, change) => {
+                return Some(change.clone())
+            }
+            MenuEntry::Toggle(_, _, on) => {
+                let flipped = !*on.borrow();
+                *on.borrow_mut() = flipped;
+            }
+            MenuEntry::Choice(..) => self.change(1.0),
+            MenuEntry::Submenu(_, _, entries) => {
+                let page = Page::new(entries.clone());
+                self.parents.push(std::mem::replace(&mut self.page, page));
+            }
+            MenuEntry::Back(..) => {
+                self.close();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Changes the toggle, slider or choice selected
+    fn change(&self, steps: f32) {
+        match self.page.current() {
+            Some(MenuEntry::Toggle(_, _, on)) => {
+                let flipped = !*on.borrow();
+                *on.borrow_mut() = flipped;
+            }
+            Some(MenuEntry::Slider(_, _, slider)) => slider.change(steps),
+            Some(MenuEntry::Choice(_, _, values, i)) if !values.is_empty() => {
+                let n = values.len() as isize;
+                let next = (*i.borrow() as isize + steps as isize).rem_euclid(n);
+                *i.borrow_mut() = next as usize;
+            }
+            _ => {}
+        }
+    }
+
+    /// Gets back from a submenu, false if there is none open
+    fn close(&mut self) -> bool {
+        match self.parents.pop() {
+            Some(page) => {
+                self.page = page;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shared, Line, Menu, MenuEntry, Slider};
+    use crate::{StateChange, States};
+    use ggez::input::keyboard::KeyCode;
+
+    fn texts(menu: &Menu) -> Vec<(String, bool)> {
+        menu.lines()
+            .into_iter()
+            .filter_map(|l| match l {
+                Line::Text { text, selected, .. } => Some((text, selected)),
+                Line::Space(_) => None,
+            })
+            .collect()
+    }
+
+    fn selected(menu: &Menu) -> String {
+        texts(menu)
+            .into_iter()
+            .find(|(_, s)| *s)
+            .map(|(t, _)| t)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn entries_are_navigated() {
+        let item = |t: &str, s: States| MenuEntry::Item(30.0, t.to_owned(), s.into());
+        let mut menu = Menu::new(vec![
+            MenuEntry::Message(60.0, "Title".to_owned()),
+            MenuEntry::VSpace(20.0),
+            item("Play", States::Game),
+            MenuEntry::List(
+                2,
+                vec![
+                    item("One", States::Courses),
+                    item("Two", States::Courses),
+                    item("Three", States::Courses),
+                ],
+            ),
+            MenuEntry::Transition(30.0, "Quit".to_owned(), KeyCode::Q, States::Quit.into()),
+        ]);
+        assert_eq!(selected(&menu), "Play");
+        // Lists show some rows, scrolling to the one selected
+        menu.key(KeyCode::Down);
+        assert_eq!(selected(&menu), "One");
+        menu.key(KeyCode::Down);
+        menu.key(KeyCode::Down);
+        assert_eq!(selected(&menu), "Three");
+        let shown: Vec<_> = texts(&menu).into_iter().map(|(t, _)| t).collect();
+        assert_eq!(shown, vec!["Title", "Play", "Two", "Three", "Quit"]);
+        menu.key(KeyCode::Down);
+        assert_eq!(selected(&menu), "Quit");
+        // Wrapping around, skipping messages
+        menu.key(KeyCode::Down);
+        assert_eq!(selected(&menu), "Play");
+        menu.key(KeyCode::Up);
+        menu.key(KeyCode::Up);
+        assert_eq!(selected(&menu), "Three");
+        assert_eq!(menu.key(KeyCode::Return), Some(States::Courses.into()));
+        assert_eq!(menu.key(KeyCode::Q), Some(States::Quit.into()));
+        menu.reset();
+        assert_eq!(selected(&menu), "Play");
+        assert_eq!(menu.key(KeyCode::Return), Some(States::Game.into()));
+    }
+
+    #[test]
+    fn enter_activates_the_entry_selected() {
+        let mut menu = Menu::new(vec![
+            MenuEntry::Transition(
+                30.0,
+                "Next".to_owned(),
+                KeyCode::Return,
+                States::Game.into(),
+            ),
+            MenuEntry::Item(30.0, "Retry".to_owned(), States::Pause.into()),
+        ]);
+        assert_eq!(menu.key(KeyCode::Return), Some(States::Game.into()));
+        menu.key(KeyCode::Down);
+        assert_eq!(menu.key(KeyCode::Return), Some(States::Pause.into()));
+        assert_eq!(menu.key(KeyCode::NumpadEnter), Some(States::Pause.into()));
+    }
+
+    #[test]
+    fn esc_leaves_pages_of_texts() {
+        let input = |label: &str| MenuEntry::Input(30.0, label.to_owned(), shared(String::new()));
+        let mut menu = Menu::new(vec![
+            input("First"),
+            input("Last"),
+            MenuEntry::Submenu(30.0, "Names".to_owned(), vec![input("One"), input("Two")]),
+        ]);
+        // A submenu of texts is closed
+        menu.key(KeyCode::Up);
+        menu.key(KeyCode::Return);
+        assert_eq!(selected(&menu), "One: _");
+        menu.key(KeyCode::Down);
+        assert_eq!(menu.key(KeyCode::Escape), None);
+        assert_eq!(selected(&menu), "Names");
+        // At the top, nothing is selected until the arrows are used
+        menu.key(KeyCode::Up);
+        assert_eq!(selected(&menu), "Last: _");
+        menu.key(KeyCode::Escape);
+        assert_eq!(selected(&menu), "");
+        menu.text('x');
+        assert_eq!(menu.key(KeyCode::Return), None);
+        menu.key(KeyCode::Up);
+        assert_eq!(selected(&menu), "Names");
+        menu.key(KeyCode::Escape);
+        menu.key(KeyCode::Down);
+        assert_eq!(selected(&menu), "First: _");
+    }
+
+    #[test]
+    fn back_closes_the_submenu() {
+        let input = |label: &str| MenuEntry::Input(30.0, label.to_owned(), shared(String::new()));
+        let mut menu = Menu::new(vec![MenuEntry::Submenu(
+            30.0,
+            "Names".to_owned(),
+            vec![input("One"), MenuEntry::Back(24.0, "Back".to_owned())],
+        )]);
+        menu.key(KeyCode::Down);
+        menu.key(KeyCode::Return);
+        menu.key(KeyCode::Up);
+        assert_eq!(selected(&menu), "Back");
+        assert_eq!(menu.key(KeyCode::Return), None);
+        assert_eq!(selected(&menu), "Names");
+    }
+
+    #[test]
+    fn settings_are_changed() {
+        let on = shared(false);
+        let size = shared(30.0);
+        let name = shared("ann".to_owned());
+        let level = shared(0);
+        let mut menu = Menu::new(vec![
+            MenuEntry::Submenu(
+                30.0,
+                "Options".to_owned(),
+                vec![
+                    MenuEntry::Toggle(30.0, "Hints".to_owned(), on.clone()),
+                    MenuEntry::Slider(
+                        30.0,
+                        "Size".to_owned(),
+                        Slider::new(size.clone(), 10.0, 40.0, 5.0),
+                    ),
+                    MenuEntry::Input(30.0, "Name".to_owned(), name.clone()),
+                    MenuEntry::Choice(
+                        30.0,
+                        "Level".to_owned(),
+                        vec!["low".to_owned(), "mid".to_owned(), "high".to_owned()],
+                        level.clone(),
+                    ),
+                ],
+            ),
+            MenuEntry::Transition(30.0, "Start".to_owned(), KeyCode::S, States::Game.into()),
+        ]);
+        menu.key(KeyCode::Return);
+        assert_eq!(selected(&menu), "Hints: off");
+        menu.key(KeyCode::Return);
+        assert!(*on.borrow());
+        menu.key(KeyCode::Right);
+        assert!(!*on.borrow());
+
+        menu.key(KeyCode::Down);
+        assert_eq!(selected(&menu), "Size: < 30 >");
+        menu.key(KeyCode::Right);
+        menu.key(KeyCode::Right);
+        menu.key(KeyCode::Right);
+        assert_eq!(*size.borrow(), 40.0);
+        menu.key(KeyCode::Left);
+        assert_eq!(*size.borrow(), 35.0);
+
+        // Hot-keys of the page are typed in text, not pressed
+        menu.key(KeyCode::Down);
+        menu.key(KeyCode::Back);
+        assert_eq!(menu.key(KeyCode::S), None);
+        menu.text('s');
+        menu.text('\r');
+        assert_eq!(selected(&menu), "Name: ans_");
+
+        // Esc leaves the text, closing the submenu
+        assert_eq!(menu.key(KeyCode::Escape), None);
+        assert_eq!(selected(&menu), "Options");
+        assert_eq!(*name.borrow(), "ans");
+        menu.key(KeyCode::Return);
+        for _ in 0..3 {
+            menu.key(KeyCode::Down);
+        }
+
+        // Choices wrap around
+        menu.key(KeyCode::Left);
+        assert_eq!(*level.borrow(), 2);
+        menu.key(KeyCode::Return);
+        assert_eq!(selected(&menu), "Level: < low >");
+        menu.key(KeyCode::Right);
+        assert_eq!(*level.borrow(), 1);
+
+        // Esc gets back to the parent menu, as it was
+        assert_eq!(menu.key(KeyCode::Escape), None);
+        assert_eq!(selected(&menu), "Options");
+        assert_eq!(menu.key(KeyCode::S), Some(StateChange::from(States::Game)));
+    }
+}
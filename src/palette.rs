@@ -0,0 +1,70 @@
+use ggez::graphics;
+use std::str::FromStr;
+
+/// A named list of named colors, as found in GIMP .gpl files
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<(graphics::Color, String)>,
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        if lines.next().map(str::trim) != Some("GIMP Palette") {
+            return Err("not a GIMP palette".to_owned());
+        }
+        let mut palette = Palette {
+            name: String::new(),
+            colors: vec![],
+        };
+        // Line numbers start from 1, and the header was skipped
+        for (n, line) in lines.enumerate().map(|(n, l)| (n + 2, l.trim())) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+                continue;
+            }
+            if line.starts_with("Name:") {
+                palette.name = line.trim_start_matches("Name:").trim().to_owned();
+                continue;
+            }
+            // Colors are three values from 0 to 255, then an optional name
+            let mut tokens = line.split_whitespace();
+            let mut rgb = [0; 3];
+            for v in rgb.iter_mut() {
+                *v = tokens
+                    .next()
+                    .and_then(|t| t.parse::<u8>().ok())
+                    .ok_or_else(|| format!("line {}: expected red, green and blue", n))?;
+            }
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            palette
+                .colors
+                .push((graphics::Color::from_rgb(rgb[0], rgb[1], rgb[2]), name));
+        }
+        Ok(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Palette;
+    use ggez::graphics;
+
+    #[test]
+    fn palette_from_gpl() {
+        let gpl = "GIMP Palette\nName: Fire\nColumns: 2\n#\n255   0   0\tRed hot\n 16 32 64\n";
+        let palette: Palette = gpl.parse().unwrap();
+        assert_eq!(palette.name, "Fire");
+        assert_eq!(
+            palette.colors,
+            vec![
+                (graphics::Color::from_rgb(255, 0, 0), "Red hot".to_owned()),
+                (graphics::Color::from_rgb(16, 32, 64), "".to_owned()),
+            ]
+        );
+        assert!("255 0 0".parse::<Palette>().is_err());
+        assert!("GIMP Palette\n255 0\n".parse::<Palette>().is_err());
+    }
+}
@@ -0,0 +1,631 @@
+//! Settings of the game, read from settings.toml in the configuration directory
+//! and changed in the settings screen
+
+use crate::difficulty::{Difficulty, Preset};
+use crate::drawing::{col2str, str2col, ColorError};
+use crate::menu::{shared, Shared, Slider};
+use crate::{MenuEntry, Payload, State, StateChange, States, UI};
+use ggez::event::EventHandler;
+use ggez::graphics::Color;
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use ggez::{Context, GameResult};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of the settings file, in the configuration directory
+const SETTINGS_FILE: &str = "settings.toml";
+/// Sections of the settings file
+const SECTIONS: [&str; 4] = ["window", "game", "custom", "colors"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// Size of the window, in pixels
+    pub width: f32,
+    pub height: f32,
+    pub fullscreen: bool,
+    /// Size of words and names in game
+    pub font_size: f32,
+    pub difficulty: Preset,
+    /// Difficulty used by the custom preset
+    pub custom: Difficulty,
+    /// Whether the pace of the game follows how the player is doing
+    pub dynamic: bool,
+    /// Fraction of keys typed right the dynamic difficulty aims at
+    pub target_accuracy: f32,
+    /// How far down the screen (as a fraction of it) the dynamic difficulty
+    /// lets enemies come
+    pub target_pressure: f32,
+    pub enemy_color: Color,
+    pub target_color: Color,
+    pub player_color: Color,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 600.0,
+            height: 800.0,
+            fullscreen: false,
+            font_size: 30.0,
+            difficulty: Preset::Normal,
+            custom: Difficulty::default(),
+            dynamic: false,
+            target_accuracy: 0.9,
+            target_pressure: 0.7,
+            enemy_color: (0xd2, 0xd5, 0x3b).into(),
+            target_color: (0xe0, 0x56, 0x2c).into(),
+            player_color: (0xff, 0x00, 0xff).into(),
+        }
+    }
+}
+
+/// Errors found reading or writing settings
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    UnknownSection(String),
+    /// Section and name of the setting
+    UnknownSetting(String, String),
+    /// Setting with a value of the wrong kind, and what it should be
+    Value(String, &'static str),
+    Color(String, ColorError),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Io(e) => write!(f, "{}", e),
+            SettingsError::Toml(e) => write!(f, "{}", e),
+            SettingsError::UnknownSection(s) => write!(f, "unknown section [{}]", s),
+            SettingsError::UnknownSetting(s, n) => write!(f, "unknown setting {} in [{}]", n, s),
+            SettingsError::Value(n, kind) => write!(f, "{} must be {}", n, kind),
+            SettingsError::Color(n, e) => write!(f, "{}: {}", n, e),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl Settings {
+    /// Reads settings from path, or from rs-type/settings.toml in the
+    /// configuration directory, if it exists. Settings not in the file keep
+    /// their defaults
+    pub fn load(path: Option<&Path>) -> Result<Self, SettingsError> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match crate::config_path(SETTINGS_FILE) {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Settings::default()),
+            },
+        };
+        std::fs::read_to_string(path)
+            .map_err(SettingsError::Io)?
+            .parse()
+    }
+
+    /// Writes settings to path, or to rs-type/settings.toml in the configuration
+    /// directory, creating it
+    pub fn save(&self, path: Option<&Path>) -> Result<(), SettingsError> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => crate::config_path(SETTINGS_FILE).ok_or_else(|| {
+                let e =
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "no configuration directory");
+                SettingsError::Io(e)
+            })?,
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(SettingsError::Io)?;
+        }
+        crate::write_atomic(&path.to_string_lossy(), &self.to_string()).map_err(SettingsError::Io)
+    }
+
+    /// Values of a difficulty preset, or of the custom one
+    pub fn difficulty_of(&self, preset: Preset) -> Difficulty {
+        preset.difficulty().unwrap_or_else(|| self.custom.clone())
+    }
+
+    /// These settings, with the ones that differ from before to after changed
+    /// to their values in after
+    fn with_changes(&self, before: &Settings, after: &Settings) -> Settings {
+        let mut settings = self.clone();
+        macro_rules! changed {
+            ($($name:ident),*) => {
+                $(if before.$name != after.$name {
+                    settings.$name = after.$name.clone();
+                })*
+            };
+        }
+        changed!(
+            width,
+            height,
+            fullscreen,
+            font_size,
+            difficulty,
+            custom,
+            dynamic,
+            target_accuracy,
+            target_pressure,
+            enemy_color,
+            target_color,
+            player_color
+        );
+        settings
+    }
+
+    /// Changes a setting from its value in the file
+    fn set(&mut self, section: &str, name: &str, value: &toml::Value) -> Result<(), SettingsError> {
+        let key = || format!("{}.{}", section, name);
+        let number = || {
+            value
+                .as_float()
+                .or_else(|| value.as_integer().map(|i| i as f64))
+        };
+        let positive = || match number() {
+            Some(x) if x > 0.0 => Ok(x as f32),
+            _ => Err(SettingsError::Value(key(), "a positive number")),
+        };
+        let not_negative = || match number() {
+            Some(x) if x >= 0.0 => Ok(x as f32),
+            _ => Err(SettingsError::Value(key(), "a number not negative")),
+        };
+        let fraction = || match number() {
+            Some(x) if x > 0.0 && x <= 1.0 => Ok(x as f32),
+            _ => Err(SettingsError::Value(key(), "a number from 0 to 1")),
+        };
+        let count = || match value.as_integer() {
+            Some(n) if n > 0 => Ok(n as usize),
+            _ => Err(SettingsError::Value(key(), "a positive integer")),
+        };
+        let flag = || {
+            value
+                .as_bool()
+                .ok_or_else(|| SettingsError::Value(key(), "true or false"))
+        };
+        let color = || match value.as_str() {
+            Some(s) => str2col(s).map_err(|e| SettingsError::Color(key(), e)),
+            None => Err(SettingsError::Value(key(), "a color")),
+        };
+        match (section, name) {
+            ("window", "width") => self.width = positive()?,
+            ("window", "height") => self.height = positive()?,
+            ("window", "fullscreen") => self.fullscreen = flag()?,
+            ("game", "font_size") => self.font_size = positive()?,
+            ("game", "difficulty") => {
+                self.difficulty = value
+                    .as_str()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| SettingsError::Value(key(), "easy, normal, hard or custom"))?
+            }
+            ("game", "dynamic") => self.dynamic = flag()?,
+            ("game", "target_accuracy") => self.target_accuracy = fraction()?,
+            ("game", "target_pressure") => self.target_pressure = fraction()?,
+            ("custom", "speed") => self.custom.speed = positive()?,
+            ("custom", "speed_growth") => self.custom.speed_growth = not_negative()?,
+            ("custom", "spawn_secs") => self.custom.spawn_secs = positive()?,
+            ("custom", "spawn_decay") => self.custom.spawn_decay = positive()?,
+            ("custom", "min_spawn_secs") => self.custom.min_spawn_secs = positive()?,
+            ("custom", "max_enemies") => self.custom.max_enemies = count()?,
+            ("custom", "length_scaling") => self.custom.length_scaling = flag()?,
+            ("colors", "enemy") => self.enemy_color = color()?,
+            ("colors", "target") => self.target_color = color()?,
+            ("colors", "player") => self.player_color = color()?,
+            _ => {
+                return Err(SettingsError::UnknownSetting(
+                    section.to_owned(),
+                    name.to_owned(),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Settings {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value: toml::Value = s.parse().map_err(SettingsError::Toml)?;
+        let table = value.as_table().expect("TOML documents are tables");
+        let mut settings = Settings::default();
+        for (section, values) in table {
+            let values = match values.as_table() {
+                Some(values) if SECTIONS.contains(&section.as_str()) => values,
+                _ => return Err(SettingsError::UnknownSection(section.clone())),
+            };
+            for (name, value) in values {
+                settings.set(section, name, value)?;
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// Settings as read from the file
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[window]")?;
+        writeln!(f, "width = {}", self.width)?;
+        writeln!(f, "height = {}", self.height)?;
+        writeln!(f, "fullscreen = {}", self.fullscreen)?;
+        writeln!(f, "\n[game]")?;
+        writeln!(f, "font_size = {}", self.font_size)?;
+        writeln!(f, "difficulty = \"{}\"", self.difficulty)?;
+        writeln!(f, "dynamic = {}", self.dynamic)?;
+        writeln!(f, "target_accuracy = {}", self.target_accuracy)?;
+        writeln!(f, "target_pressure = {}", self.target_pressure)?;
+        writeln!(f, "\n[custom]")?;
+        writeln!(f, "speed = {}", self.custom.speed)?;
+        writeln!(f, "speed_growth = {}", self.custom.speed_growth)?;
+        writeln!(f, "spawn_secs = {}", self.custom.spawn_secs)?;
+        writeln!(f, "spawn_decay = {}", self.custom.spawn_decay)?;
+        writeln!(f, "min_spawn_secs = {}", self.custom.min_spawn_secs)?;
+        writeln!(f, "max_enemies = {}", self.custom.max_enemies)?;
+        writeln!(f, "length_scaling = {}", self.custom.length_scaling)?;
+        writeln!(f, "\n[colors]")?;
+        writeln!(f, "enemy = \"{}\"", col2str(self.enemy_color))?;
+        writeln!(f, "target = \"{}\"", col2str(self.target_color))?;
+        writeln!(f, "player = \"{}\"", col2str(self.player_color))
+    }
+}
+
+/// Values of the settings being changed, shared with the entries showing them
+struct Fields {
+    width: Shared<f32>,
+    height: Shared<f32>,
+    fullscreen: Shared<bool>,
+    font_size: Shared<f32>,
+    // Index of the preset in Preset::ALL, shared with the start screen too
+    difficulty: Shared<usize>,
+    speed: Shared<f32>,
+    speed_growth: Shared<f32>,
+    spawn_secs: Shared<f32>,
+    spawn_decay: Shared<f32>,
+    min_spawn_secs: Shared<f32>,
+    max_enemies: Shared<f32>,
+    length_scaling: Shared<bool>,
+    dynamic: Shared<bool>,
+    target_accuracy: Shared<f32>,
+    target_pressure: Shared<f32>,
+    enemy_color: Shared<String>,
+    target_color: Shared<String>,
+    player_color: Shared<String>,
+}
+
+impl Fields {
+    fn new(settings: &Settings, difficulty: Shared<usize>) -> Fields {
+        let custom = &settings.custom;
+        Fields {
+            width: shared(settings.width),
+            height: shared(settings.height),
+            fullscreen: shared(settings.fullscreen),
+            font_size: shared(settings.font_size),
+            difficulty,
+            speed: shared(custom.speed),
+            speed_growth: shared(custom.speed_growth),
+            spawn_secs: shared(custom.spawn_secs),
+            spawn_decay: shared(custom.spawn_decay),
+            min_spawn_secs: shared(custom.min_spawn_secs),
+            max_enemies: shared(custom.max_enemies as f32),
+            length_scaling: shared(custom.length_scaling),
+            dynamic: shared(settings.dynamic),
+            target_accuracy: shared(settings.target_accuracy),
+            target_pressure: shared(settings.target_pressure),
+            enemy_color: shared(col2str(settings.enemy_color)),
+            target_color: shared(col2str(settings.target_color)),
+            player_color: shared(col2str(settings.player_color)),
+        }
+    }
+
+    /// Shows the values of settings, but the difficulty which might have been
+    /// changed in the start screen
+    fn load(&self, settings: &Settings) {
+        let custom = &settings.custom;
+        *self.width.borrow_mut() = settings.width;
+        *self.height.borrow_mut() = settings.height;
+        *self.fullscreen.borrow_mut() = settings.fullscreen;
+        *self.font_size.borrow_mut() = settings.font_size;
+        *self.speed.borrow_mut() = custom.speed;
+        *self.speed_growth.borrow_mut() = custom.speed_growth;
+        *self.spawn_secs.borrow_mut() = custom.spawn_secs;
+        *self.spawn_decay.borrow_mut() = custom.spawn_decay;
+        *self.min_spawn_secs.borrow_mut() = custom.min_spawn_secs;
+        *self.max_enemies.borrow_mut() = custom.max_enemies as f32;
+        *self.length_scaling.borrow_mut() = custom.length_scaling;
+        *self.dynamic.borrow_mut() = settings.dynamic;
+        *self.target_accuracy.borrow_mut() = settings.target_accuracy;
+        *self.target_pressure.borrow_mut() = settings.target_pressure;
+        *self.enemy_color.borrow_mut() = col2str(settings.enemy_color);
+        *self.target_color.borrow_mut() = col2str(settings.target_color);
+        *self.player_color.borrow_mut() = col2str(settings.player_color);
+    }
+
+    /// Changes settings to the values shown, keeping colors that can't be read
+    fn store(&self, settings: &mut Settings) {
+        settings.width = *self.width.borrow();
+        settings.height = *self.height.borrow();
+        settings.fullscreen = *self.fullscreen.borrow();
+        settings.font_size = *self.font_size.borrow();
+        settings.difficulty = Preset::ALL[*self.difficulty.borrow()];
+        settings.custom = Difficulty {
+            speed: *self.speed.borrow(),
+            speed_growth: *self.speed_growth.borrow(),
+            spawn_secs: *self.spawn_secs.borrow(),
+            spawn_decay: *self.spawn_decay.borrow(),
+            min_spawn_secs: *self.min_spawn_secs.borrow(),
+            max_enemies: *self.max_enemies.borrow() as usize,
+            length_scaling: *self.length_scaling.borrow(),
+        };
+        settings.dynamic = *self.dynamic.borrow();
+        settings.target_accuracy = *self.target_accuracy.borrow();
+        settings.target_pressure = *self.target_pressure.borrow();
+        let color = |text: &Shared<String>, color: &mut Color| match str2col(&text.borrow()) {
+            Ok(c) => *color = c,
+            Err(e) => println!("Color {:?} not changed: {}", text.borrow(), e),
+        };
+        color(&self.enemy_color, &mut settings.enemy_color);
+        color(&self.target_color, &mut settings.target_color);
+        color(&self.player_color, &mut settings.player_color);
+    }
+
+    /// Entries changing the values
+    fn entries(&self) -> Vec<MenuEntry> {
+        let slider = |label: &str, value: &Shared<f32>, min, max, step| {
+            let slider = Slider::new(value.clone(), min, max, step);
+            MenuEntry::Slider(30.0, label.to_owned(), slider)
+        };
+        let color = |label: &str, value: &Shared<String>| {
+            MenuEntry::Input(30.0, label.to_owned(), value.clone())
+        };
+        let submenu = |title: &str, mut entries: Vec<MenuEntry>| {
+            let mut page = vec![
+                MenuEntry::VSpace(120.0),
+                MenuEntry::Message(50.0, title.to_owned()),
+                MenuEntry::VSpace(30.0),
+            ];
+            page.append(&mut entries);
+            page.push(MenuEntry::VSpace(30.0));
+            page.push(MenuEntry::Back(24.0, "[Esc] back".to_owned()));
+            MenuEntry::Submenu(30.0, title.to_owned(), page)
+        };
+        vec![
+            MenuEntry::VSpace(120.0),
+            MenuEntry::Message(50.0, "Options".to_owned()),
+            MenuEntry::VSpace(30.0),
+            slider("Font size", &self.font_size, 10.0, 60.0, 2.0),
+            difficulty_choice(30.0, &self.difficulty),
+            submenu(
+                "Custom difficulty",
+                vec![
+                    slider("Speed", &self.speed, 0.1, 3.0, 0.1),
+                    slider("Speed growth", &self.speed_growth, 0.0, 2.0, 0.1),
+                    slider("Seconds between", &self.spawn_secs, 0.5, 5.0, 0.5),
+                    slider("Seconds decay", &self.spawn_decay, 0.5, 1.0, 0.05),
+                    slider("Fewest seconds", &self.min_spawn_secs, 0.5, 5.0, 0.5),
+                    slider("Max enemies", &self.max_enemies, 1.0, 20.0, 1.0),
+                    MenuEntry::Toggle(
+                        30.0,
+                        "Slower long words".to_owned(),
+                        self.length_scaling.clone(),
+                    ),
+                ],
+            ),
+            submenu(
+                "Dynamic difficulty",
+                vec![
+                    MenuEntry::Toggle(30.0, "Follow the player".to_owned(), self.dynamic.clone()),
+                    slider("Target accuracy", &self.target_accuracy, 0.5, 1.0, 0.05),
+                    slider("Target pressure", &self.target_pressure, 0.3, 0.9, 0.05),
+                ],
+            ),
+            submenu(
+                "Colors",
+                vec![
+                    color("Enemies", &self.enemy_color),
+                    color("Target", &self.target_color),
+                    color("Player", &self.player_color),
+                ],
+            ),
+            submenu(
+                "Window",
+                vec![
+                    slider("Width", &self.width, 400.0, 1920.0, 20.0),
+                    slider("Height", &self.height, 400.0, 1200.0, 20.0),
+                    MenuEntry::Toggle(30.0, "Fullscreen".to_owned(), self.fullscreen.clone()),
+                    MenuEntry::VSpace(30.0),
+                    MenuEntry::Message(24.0, "Changed at next start".to_owned()),
+                ],
+            ),
+            MenuEntry::VSpace(30.0),
+            MenuEntry::Transition(
+                24.0,
+                "[Esc] save and go back".to_owned(),
+                KeyCode::Escape,
+                States::StartScreen.into(),
+            ),
+        ]
+    }
+}
+
+/// Entry choosing the difficulty preset, by its index in Preset::ALL
+pub fn difficulty_choice(scale: f32, preset: &Shared<usize>) -> MenuEntry {
+    let names = Preset::ALL.iter().map(Preset::to_string).collect();
+    MenuEntry::Choice(scale, "Difficulty".to_owned(), names, preset.clone())
+}
+
+/// Screen changing the settings shared with the game, saving them when left
+pub struct SettingsScreen {
+    ui: UI,
+    fields: Fields,
+    // Settings used, which options given on the command line might override
+    settings: Shared<Settings>,
+    // Settings as in the file, which are saved with the changes made here
+    file: Settings,
+    // Where settings are saved, instead of the configuration directory
+    path: Option<PathBuf>,
+}
+
+impl SettingsScreen {
+    /// Screen changing settings, as read from the file and as used, and the
+    /// difficulty preset chosen, by its index in Preset::ALL
+    pub fn new(
+        ctx: &mut Context,
+        file: Settings,
+        settings: Shared<Settings>,
+        difficulty: Shared<usize>,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let fields = Fields::new(&settings.borrow(), difficulty);
+        SettingsScreen {
+            ui: UI::new(ctx, fields.entries()),
+            fields,
+            settings,
+            file,
+            path,
+        }
+    }
+}
+
+impl EventHandler for SettingsScreen {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.ui.update(ctx)
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        self.ui.draw(ctx)
+    }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        keycode: KeyCode,
+        keymods: KeyMods,
+        repeat: bool,
+    ) {
+        self.ui.key_down_event(ctx, keycode, keymods, repeat)
+    }
+
+    fn text_input_event(&mut self, ctx: &mut Context, ch: char) {
+        self.ui.text_input_event(ctx, ch)
+    }
+
+    fn quit_event(&mut self, ctx: &mut Context) -> bool {
+        self.ui.quit_event(ctx)
+    }
+}
+
+impl State for SettingsScreen {
+    fn next_state(&mut self) -> Option<StateChange> {
+        self.ui.next_state()
+    }
+
+    fn on_enter(&mut self, ctx: &mut Context, payload: Payload) {
+        self.fields.load(&self.settings.borrow());
+        self.ui.on_enter(ctx, payload);
+    }
+
+    /// Applies the settings changed and saves them, but not the values given
+    /// on the command line that were left as they were
+    fn on_exit(&mut self, _ctx: &mut Context) {
+        let mut settings = self.settings.borrow_mut();
+        let before = settings.clone();
+        self.fields.store(&mut settings);
+        self.file = self.file.with_changes(&before, &settings);
+        if let Err(e) = self.file.save(self.path.as_deref()) {
+            println!("Unable to save settings: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fields, Settings, SettingsError};
+    use crate::difficulty::Preset;
+    use crate::menu::shared;
+
+    #[test]
+    fn settings_are_read_and_written() {
+        let default = Settings::default();
+        assert_eq!(default.to_string().parse::<Settings>().unwrap(), default);
+
+        let settings: Settings = "[window]\nwidth = 1024\nfullscreen = true\n\
+                                  [colors]\ntarget = \"orange\"\n"
+            .parse()
+            .unwrap();
+        assert_eq!(settings.width, 1024.0);
+        assert!(settings.fullscreen);
+        assert_eq!(settings.target_color, (0xff, 0xa5, 0x00).into());
+        // Settings not in the file keep their defaults
+        assert_eq!(settings.height, default.height);
+        assert_eq!(settings.to_string().parse::<Settings>().unwrap(), settings);
+
+        let settings: Settings = "[game]\ndifficulty = \"hard\"\n[custom]\nmax_enemies = 3\n"
+            .parse()
+            .unwrap();
+        assert_eq!(settings.difficulty, Preset::Hard);
+        assert_eq!(settings.difficulty_of(Preset::Custom).max_enemies, 3);
+
+        let err = |s: &str| s.parse::<Settings>().unwrap_err();
+        assert!(matches!(
+            err("[sound]\n"),
+            SettingsError::UnknownSection(..)
+        ));
+        assert!(matches!(
+            err("[game]\nlives = 3\n"),
+            SettingsError::UnknownSetting(..)
+        ));
+        assert!(matches!(
+            err("[custom]\nspeed = -1\n"),
+            SettingsError::Value(..)
+        ));
+        assert!(matches!(
+            err("[colors]\nenemy = \"#12\"\n"),
+            SettingsError::Color(..)
+        ));
+        assert!(matches!(
+            err("[game]\ntarget_pressure = 1.5\n"),
+            SettingsError::Value(..)
+        ));
+    }
+
+    #[test]
+    fn fields_keep_colors_that_cant_be_read() {
+        let mut settings = Settings::default();
+        let fields = Fields::new(&settings, shared(Preset::Hard.index()));
+        *fields.speed.borrow_mut() = 1.5;
+        *fields.enemy_color.borrow_mut() = "white".to_owned();
+        *fields.player_color.borrow_mut() = "nope".to_owned();
+        fields.store(&mut settings);
+        assert_eq!(settings.custom.speed, 1.5);
+        assert_eq!(settings.difficulty, Preset::Hard);
+        assert_eq!(settings.enemy_color, (0xff, 0xff, 0xff).into());
+        assert_eq!(settings.player_color, Settings::default().player_color);
+    }
+
+    #[test]
+    fn only_changes_are_saved() {
+        let file: Settings = "[window]\nfullscreen = true\n".parse().unwrap();
+        // Given on the command line
+        let before = Settings {
+            fullscreen: false,
+            width: 1024.0,
+            ..file.clone()
+        };
+        let after = Settings {
+            font_size: 40.0,
+            ..before.clone()
+        };
+        let saved = file.with_changes(&before, &after);
+        assert!(saved.fullscreen);
+        assert_eq!(saved.width, file.width);
+        assert_eq!(saved.font_size, 40.0);
+        // Unless they are changed in the options
+        let after = Settings {
+            width: 800.0,
+            ..before.clone()
+        };
+        assert_eq!(file.with_changes(&before, &after).width, 800.0);
+    }
+}
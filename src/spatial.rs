@@ -0,0 +1,152 @@
+use ggez::nalgebra as na;
+use std::collections::HashMap;
+
+/// Coordinates of the cell of side size containing point
+fn cell_of(size: f32, p: &na::Point2<f32>) -> (i32, i32) {
+    ((p.x / size).floor() as i32, (p.y / size).floor() as i32)
+}
+
+/// Coordinates of the cells of side size covered by the box with corners min and max
+fn cells_in(
+    size: f32,
+    min: &na::Point2<f32>,
+    max: &na::Point2<f32>,
+) -> impl Iterator<Item = (i32, i32)> {
+    let (x0, y0) = cell_of(size, min);
+    let (x1, y1) = cell_of(size, max);
+    (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+}
+
+/// A uniform grid of square cells, each holding the items whose bounding box
+/// covers that cell
+#[derive(Clone, Debug)]
+pub struct Grid<T> {
+    /// Side of cells
+    size: f32,
+    /// Items in every non-empty cell
+    cells: HashMap<(i32, i32), Vec<T>>,
+}
+
+impl<T: Copy + PartialEq> Grid<T> {
+    pub fn new(size: f32) -> Self {
+        Grid {
+            size,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Side of cells
+    pub fn size(&self) -> f32 {
+        self.size
+    }
+
+    /// Coordinates of the cell containing point
+    pub fn cell_of(&self, p: &na::Point2<f32>) -> (i32, i32) {
+        cell_of(self.size, p)
+    }
+
+    /// Number of non-empty cells
+    pub fn occupied(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Adds item to every cell covered by the box with corners min and max
+    pub fn insert(&mut self, item: T, min: &na::Point2<f32>, max: &na::Point2<f32>) {
+        for c in cells_in(self.size, min, max) {
+            self.cells.entry(c).or_default().push(item);
+        }
+    }
+
+    /// Removes item from every cell covered by the box with corners min and max
+    pub fn remove(&mut self, item: T, min: &na::Point2<f32>, max: &na::Point2<f32>) {
+        for c in cells_in(self.size, min, max) {
+            if let Some(items) = self.cells.get_mut(&c) {
+                if let Some(i) = items.iter().position(|x| *x == item) {
+                    items.swap_remove(i);
+                }
+                if items.is_empty() {
+                    self.cells.remove(&c);
+                }
+            }
+        }
+    }
+
+    /// Replaces every item with f(item), dropping those mapped to None. Cheaper
+    /// than removing and inserting when many items change at once
+    pub fn retain_map<F: FnMut(T) -> Option<T>>(&mut self, mut f: F) {
+        self.cells.retain(|_, items| {
+            *items = items.iter().filter_map(|&x| f(x)).collect();
+            !items.is_empty()
+        });
+    }
+
+    /// Items in the cell containing point
+    pub fn at(&self, p: &na::Point2<f32>) -> &[T] {
+        self.cells
+            .get(&self.cell_of(p))
+            .map_or(&[], |items| items.as_slice())
+    }
+
+    /// Items in the cells covered by the box with corners min and max, an
+    /// item covering more cells is repeated
+    pub fn within(&self, min: &na::Point2<f32>, max: &na::Point2<f32>) -> Vec<T> {
+        cells_in(self.size, min, max)
+            .filter_map(|c| self.cells.get(&c))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Items in the cells at (chessboard) distance r from cell c
+    pub fn ring(&self, c: (i32, i32), r: i32) -> Vec<T> {
+        let (x, y) = c;
+        let cells: Vec<(i32, i32)> = if r == 0 {
+            vec![c]
+        } else {
+            (-r..=r)
+                .flat_map(|d| vec![(x + d, y - r), (x + d, y + r)])
+                .chain((1 - r..r).flat_map(|d| vec![(x - r, y + d), (x + r, y + d)]))
+                .collect()
+        };
+        cells
+            .iter()
+            .filter_map(|c| self.cells.get(c))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Removes every item, changing the side of cells
+    pub fn reset(&mut self, size: f32) {
+        self.size = size;
+        self.cells.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Grid;
+    use ggez::nalgebra as na;
+
+    #[test]
+    fn grid_buckets_boxes() {
+        let mut grid = Grid::new(10.0);
+        grid.insert(1, &na::Point2::new(5.0, 5.0), &na::Point2::new(15.0, 5.0));
+        grid.insert(
+            2,
+            &na::Point2::new(-5.0, -5.0),
+            &na::Point2::new(-5.0, -5.0),
+        );
+        assert_eq!(grid.occupied(), 3);
+        assert_eq!(grid.at(&na::Point2::new(12.0, 1.0)), &[1]);
+        assert_eq!(grid.ring((0, 0), 0), vec![1]);
+        assert_eq!(grid.ring((0, 0), 1), vec![2, 1]);
+        grid.remove(1, &na::Point2::new(5.0, 5.0), &na::Point2::new(15.0, 5.0));
+        assert_eq!(grid.occupied(), 1);
+        assert!(grid.at(&na::Point2::new(12.0, 1.0)).is_empty());
+        grid.insert(3, &na::Point2::new(5.0, 5.0), &na::Point2::new(5.0, 5.0));
+        grid.retain_map(|x| if x == 2 { None } else { Some(x * 10) });
+        assert_eq!(grid.occupied(), 1);
+        assert_eq!(grid.at(&na::Point2::new(5.0, 5.0)), &[30]);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::wording::Lesson;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Characters per word, to compute words per minute
+pub const WORD_LENGTH: f32 = 5.0;
+
+/// Keystrokes of a level, telling how well it was typed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Characters typed right
+    pub hits: usize,
+    /// Characters typed wrong, by the character that should have been typed
+    /// (or the one typed, if it didn't start any word)
+    pub misses: BTreeMap<char, usize>,
+    /// Time spent playing
+    pub time: Duration,
+}
+
+impl Stats {
+    pub fn hit(&mut self) {
+        self.hits += 1;
+    }
+
+    pub fn miss(&mut self, ch: char) {
+        *self.misses.entry(ch).or_insert(0) += 1;
+    }
+
+    /// Number of characters typed wrong
+    pub fn missed(&self) -> usize {
+        self.misses.values().sum()
+    }
+
+    /// Words (of 5 characters) typed right per minute
+    pub fn wpm(&self) -> f32 {
+        let minutes = self.time.as_secs_f32() / 60.0;
+        if minutes > 0.0 {
+            self.hits as f32 / WORD_LENGTH / minutes
+        } else {
+            0.0
+        }
+    }
+
+    /// Fraction of characters typed right, 1 if nothing was typed
+    pub fn accuracy(&self) -> f32 {
+        let typed = self.hits + self.missed();
+        if typed > 0 {
+            self.hits as f32 / typed as f32
+        } else {
+            1.0
+        }
+    }
+
+    /// Characters typed wrong, most missed first
+    pub fn worst(&self) -> Vec<(char, usize)> {
+        let mut misses: Vec<_> = self.misses.iter().map(|(&c, &n)| (c, n)).collect();
+        misses.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+        misses
+    }
+}
+
+/// Time, speed and accuracy in a line, then the characters missed
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.time.as_secs();
+        writeln!(
+            f,
+            "Time {}:{:02} | {:.0} WPM | {:.0}% accuracy",
+            secs / 60,
+            secs % 60,
+            self.wpm(),
+            self.accuracy() * 100.0,
+        )?;
+        if self.misses.is_empty() {
+            write!(f, "No misses")
+        } else {
+            let worst: Vec<String> = self
+                .worst()
+                .iter()
+                .map(|(c, n)| format!("{} x{}", c, n))
+                .collect();
+            write!(f, "Missed {}", worst.join(", "))
+        }
+    }
+}
+
+/// How a level went, and what the next one is about
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelReport {
+    pub stats: Stats,
+    /// Description of the next level, if there is one and it's described
+    pub next: Option<Lesson>,
+}
+
+impl fmt::Display for LevelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.stats)?;
+        if let Some(next) = &self.next {
+            write!(f, "\n\nNext: {}", next.title)?;
+            if !next.new_chars.is_empty() {
+                write!(f, "\nNew characters: {}", next.new_chars)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LevelReport, Stats};
+    use crate::wording::Lesson;
+    use std::time::Duration;
+
+    #[test]
+    fn stats_of_a_level() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.wpm(), 0.0);
+        assert_eq!(stats.accuracy(), 1.0);
+        for _ in 0..90 {
+            stats.hit();
+        }
+        stats.miss('f');
+        stats.miss('j');
+        stats.miss('f');
+        stats.time = Duration::from_secs(90);
+        // 90 characters are 18 words, in one minute and a half
+        assert!((stats.wpm() - 12.0).abs() < 1e-4);
+        assert!((stats.accuracy() - 90.0 / 93.0).abs() < 1e-6);
+        assert_eq!(stats.worst(), vec![('f', 2), ('j', 1)]);
+        assert_eq!(
+            stats.to_string(),
+            "Time 1:30 | 12 WPM | 97% accuracy\nMissed f x2, j x1"
+        );
+
+        let report = LevelReport {
+            stats: Stats::default(),
+            next: Some(Lesson {
+                title: "Lesson 2".to_owned(),
+                new_chars: "dk".to_owned(),
+            }),
+        };
+        assert_eq!(
+            report.to_string(),
+            "Time 0:00 | 0 WPM | 100% accuracy\nNo misses\n\nNext: Lesson 2\nNew characters: dk"
+        );
+    }
+}
@@ -1,19 +1,82 @@
-use std::path::Path;
+use ggez::input::keyboard::KeyCode;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 static DICTIONARY: &[&str] = &[
     "jj", "ff", "jj", "ff", "jjj", "fff", "jfj", "fjj", "ffj", "jff", "ciao", "belli", "come",
     "state",
 ];
 
+/// Characters standing for keys that type nothing, in words to type
+pub const ENTER: char = '\u{23ce}';
+pub const BACKSPACE: char = '\u{232b}';
+pub const CAPS_LOCK: char = '\u{21ea}';
+pub const ESCAPE: char = '\u{238b}';
+
+/// Key tokens, how courses write them and the keys typing them
+const KEY_TOKENS: [(char, &str, &[KeyCode]); 4] = [
+    (ENTER, "{Enter}", &[KeyCode::Return, KeyCode::NumpadEnter]),
+    (BACKSPACE, "{Backspace}", &[KeyCode::Back]),
+    (CAPS_LOCK, "{CapsLock}", &[KeyCode::Capital]),
+    (ESCAPE, "{Esc}", &[KeyCode::Escape]),
+];
+
+/// The token typed by a key, if it stands for one
+pub fn key_token(code: KeyCode) -> Option<char> {
+    KEY_TOKENS
+        .iter()
+        .find(|(_, _, keys)| keys.contains(&code))
+        .map(|&(token, _, _)| token)
+}
+
+/// Text with the names of key tokens, like {Enter}, replaced by the tokens
+pub fn expand_key_tokens(text: &str) -> String {
+    KEY_TOKENS
+        .iter()
+        .fold(text.to_owned(), |text, (token, name, _)| {
+            text.replace(name, &token.to_string())
+        })
+}
+
+/// Where the words of a game come from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Course {
+    /// A course file
+    File(PathBuf),
+    /// The built-in vim drills
+    Vim,
+}
+
+impl fmt::Display for Course {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Course::File(path) => write!(f, "{}", path.display()),
+            Course::Vim => write!(f, "vim drills"),
+        }
+    }
+}
+
 pub enum Enemies {
     Some(String),
     LevelComplete,
     GameComplete,
 }
 
+/// Title of a level and characters it introduces, as courses describe lessons
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lesson {
+    pub title: String,
+    pub new_chars: String,
+}
+
 pub trait WordProducer {
     /// Produces the n-th word of the l-th level
     fn next_word(&mut self, l: usize, n: usize) -> Enemies;
+
+    /// Describes the l-th level, if there is a description
+    fn lesson(&self, _l: usize) -> Option<Lesson> {
+        None
+    }
 }
 
 pub struct CodeDict;
@@ -31,42 +94,170 @@ impl WordProducer for CodeDict {
     }
 }
 
+/// Lessons of the vim drills: title, new keys, and the commands to type, with
+/// key tokens written by name
+static VIM_LESSONS: &[(&str, &str, &[&str])] = &[
+    (
+        "Moving around",
+        "hjklwbe0$",
+        &[
+            "j", "k", "h", "l", "w", "b", "e", "0", "$", "jj", "kk", "ww", "bb", "e", "$", "0",
+        ],
+    ),
+    (
+        "Jumping",
+        "gGfFtT%",
+        &[
+            "gg", "G", "fx", "F(", "t;", "T,", "%", "f)", "gg", "G", "t\"", "F[",
+        ],
+    ),
+    (
+        "Deleting",
+        "dxD",
+        &[
+            "x", "dd", "dw", "db", "D", "d$", "d0", "dj", "dk", "X", "de", "dG", "dt;", "df)",
+        ],
+    ),
+    (
+        "Changing text objects",
+        "ciaws\"()[]",
+        &[
+            "cw", "ciw", "ci\"", "ci(", "ca[", "cc", "C", "daw", "diw", "da\"", "yiw", "cis", "dip",
+        ],
+    ),
+    (
+        "Back to normal mode",
+        "{Esc}",
+        &[
+            "i{Esc}", "a{Esc}", "o{Esc}", "O{Esc}", "A{Esc}", "I{Esc}", "{Esc}", "cw{Esc}",
+            "ciw{Esc}", "s{Esc}", "S{Esc}", "cc{Esc}",
+        ],
+    ),
+    (
+        "Commands",
+        ":/?!{Enter}",
+        &[
+            ":w{Enter}",
+            ":q{Enter}",
+            ":wq{Enter}",
+            ":q!{Enter}",
+            ":x{Enter}",
+            "/foo{Enter}",
+            "?bar{Enter}",
+            ":e!{Enter}",
+            ":%s/a/b/g{Enter}",
+            ":set{Enter}",
+            "{Esc}:wq{Enter}",
+        ],
+    ),
+    (
+        "Counts and repeats",
+        "123456789.uyp",
+        &[
+            "3w", "5j", "2dd", "d3w", "4x", "yy", "p", "P", "u", ".", "3.", "2u", "y2w", "10G",
+            "c2w{Esc}", "3dd", "5k",
+        ],
+    ),
+];
+
+/// Built-in course of vim commands, from motions to ex commands, with Esc and
+/// Enter to type as well
+pub struct VimDrills;
+
+impl WordProducer for VimDrills {
+    fn next_word(&mut self, l: usize, n: usize) -> Enemies {
+        match VIM_LESSONS.get(l) {
+            Some((_, _, words)) => match words.get(n) {
+                Some(word) => Enemies::Some(expand_key_tokens(word)),
+                None => Enemies::LevelComplete,
+            },
+            None => Enemies::GameComplete,
+        }
+    }
+
+    fn lesson(&self, l: usize) -> Option<Lesson> {
+        VIM_LESSONS.get(l).map(|(title, new_chars, _)| Lesson {
+            title: (*title).to_owned(),
+            new_chars: expand_key_tokens(new_chars),
+        })
+    }
+}
+
+/// Any producer behind a box, to pick one at runtime
+impl<P: WordProducer + ?Sized> WordProducer for Box<P> {
+    fn next_word(&mut self, l: usize, n: usize) -> Enemies {
+        (**self).next_word(l, n)
+    }
+
+    fn lesson(&self, l: usize) -> Option<Lesson> {
+        (**self).lesson(l)
+    }
+}
+
 pub struct KTouchParser {
     lessons: Vec<Vec<String>>,
+    titles: Vec<Lesson>,
 }
 
 impl KTouchParser {
     pub fn new(src: &Path) -> Self {
-        let contents = std::fs::read_to_string(src).expect("Cannot open file");
+        Self::load(src).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Reads a KTouch course, failing if it can't be read or isn't a course
+    pub fn load(src: &Path) -> Result<Self, String> {
+        let contents =
+            std::fs::read_to_string(src).map_err(|e| format!("Cannot open file: {}", e))?;
 
-        let doc = roxmltree::Document::parse(&contents).unwrap();
+        let doc = roxmltree::Document::parse(&contents).map_err(|e| e.to_string())?;
         let lessons = doc
             .root()
             .first_element_child()
-            .expect("Unable to find course root")
+            .ok_or("Unable to find course root")?
             .children()
             .find(|n| n.tag_name().name() == "lessons")
-            .expect("Unable to find lessons tag");
+            .ok_or("Unable to find lessons tag")?;
 
         let lessons = lessons
             .children()
             .filter(roxmltree::Node::is_element)
             .take(3)
+            .collect::<Vec<_>>();
+        // Text of a child of the lesson, empty if missing
+        let child = |lesson: &roxmltree::Node, name: &str| {
+            lesson
+                .children()
+                .find(|c| c.tag_name().name() == name)
+                .and_then(|c| c.text())
+                .unwrap_or("")
+                .trim()
+                .to_owned()
+        };
+        let titles = lessons
+            .iter()
+            .map(|lesson| Lesson {
+                title: child(lesson, "title"),
+                new_chars: expand_key_tokens(&child(lesson, "newCharacters")),
+            })
+            .collect();
+
+        let lessons = lessons
+            .iter()
             .map(|lesson| {
-                lesson
+                Ok(lesson
                     .children()
                     .filter(roxmltree::Node::is_element)
                     .find(|c| c.tag_name().name() == "text")
-                    .expect("Unable to find lesson's text")
+                    .ok_or("Unable to find lesson's text")?
                     .text()
-                    .expect("There is no text in this lesson!")
+                    .ok_or("There is no text in this lesson!")?
                     .split_whitespace()
-                    .map(str::to_owned)
-                    .collect::<Vec<_>>()
+                    .map(expand_key_tokens)
+                    .collect::<Vec<_>>())
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, String>>()?;
 
-        KTouchParser { lessons }
+        Ok(KTouchParser { lessons, titles })
     }
 }
 
@@ -82,4 +273,60 @@ impl WordProducer for KTouchParser {
             Enemies::GameComplete
         }
     }
+
+    fn lesson(&self, l: usize) -> Option<Lesson> {
+        self.titles.get(l).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_key_tokens, key_token, Enemies, VimDrills, WordProducer, ENTER, ESCAPE};
+    use ggez::input::keyboard::KeyCode;
+
+    #[test]
+    fn key_tokens() {
+        assert_eq!(expand_key_tokens(":wq{Enter}"), format!(":wq{}", ENTER));
+        assert_eq!(
+            expand_key_tokens("{Esc}{Esc}"),
+            format!("{}{}", ESCAPE, ESCAPE)
+        );
+        assert_eq!(expand_key_tokens("{enter}"), "{enter}");
+        assert_eq!(key_token(KeyCode::NumpadEnter), Some(ENTER));
+        assert_eq!(key_token(KeyCode::Escape), Some(ESCAPE));
+        assert_eq!(key_token(KeyCode::A), None);
+    }
+
+    #[test]
+    fn vim_drills() {
+        let mut drills = VimDrills;
+        let mut levels = 0;
+        loop {
+            let mut words = Vec::new();
+            loop {
+                match drills.next_word(levels, words.len()) {
+                    Enemies::Some(word) => words.push(word),
+                    Enemies::LevelComplete => break,
+                    Enemies::GameComplete => {
+                        assert!(words.is_empty());
+                        assert_eq!(drills.lesson(levels), None);
+                        assert!(levels > 1);
+                        return;
+                    }
+                }
+            }
+            assert!(!words.is_empty());
+            // Every key token is written by name right
+            assert!(words.iter().all(|w| !w.contains('{')));
+            let lesson = drills.lesson(levels).unwrap();
+            assert!(!lesson.title.is_empty());
+            if lesson.new_chars.contains(ESCAPE) {
+                assert!(words.iter().all(|w| w.contains(ESCAPE)));
+            }
+            if lesson.new_chars.contains(ENTER) {
+                assert!(words.iter().all(|w| w.ends_with(ENTER)));
+            }
+            levels += 1;
+        }
+    }
 }